@@ -21,6 +21,7 @@
             pkgsCross.riscv32.stdenv.cc
             pkgsCross.riscv64.stdenv.cc
             dtc
+            python3
           ];
         };
       };
@@ -0,0 +1,380 @@
+//! The allocation list behind `kmalloc`.
+//!
+//! A list covers a contiguous region of memory. It starts with a node at the
+//! start of the region and every node is followed by the next one, so the whole
+//! region is split into nodes. Allocations are the memory right behind the
+//! header of a taken node.
+use core::mem::size_of;
+use crate::align_up;
+
+/// Size of the header in front of every node.
+pub const HEADER_SIZE: usize = size_of::<AllocList>();
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy)]
+// The taken bit is the most significant bit on every target.
+#[allow(clippy::enum_clike_unportable_variant)]
+pub enum AllocListFlags {
+    Taken = 1 << (usize::BITS - 1),
+}
+
+impl AllocListFlags {
+    pub fn bits(self) -> usize {
+        self as usize
+    }
+}
+
+/// A structure representing a node in the allocation list.
+/// The allocation list knows where the next node is located
+/// and whether the current node is taken or free because
+/// it stores the taken bit in the most significant bit of the size field
+/// and the size of the allocation in the remaining bits.
+///
+/// Meaning the next node is located at the address of the current node
+/// plus the size of the current node.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocList {
+    flags_size: usize,
+}
+
+impl AllocList {
+    /// Writes a single free node spanning `size` bytes at `head`.
+    /// # Safety
+    /// `head` must be 8 byte aligned and valid for `size` bytes.
+    pub unsafe fn init(head: *mut AllocList, size: usize) {
+        head.write(AllocList { flags_size: 0 });
+        (*head).set_size(size);
+    }
+
+    pub fn is_taken(&self) -> bool {
+        self.flags_size & AllocListFlags::Taken.bits() != 0
+    }
+
+    pub fn is_free(&self) -> bool {
+        !self.is_taken()
+    }
+
+    pub fn set_taken(&mut self) {
+        self.flags_size |= AllocListFlags::Taken.bits();
+    }
+
+    pub fn set_free(&mut self) {
+        self.flags_size &= !AllocListFlags::Taken.bits();
+    }
+
+    pub fn size(&self) -> usize {
+        self.flags_size & !AllocListFlags::Taken.bits()
+    }
+
+    pub fn set_size(&mut self, size: usize) {
+        let k = self.is_taken();
+        self.flags_size = size & !AllocListFlags::Taken.bits();
+        if k {
+            self.flags_size |= AllocListFlags::Taken.bits();
+        }
+    }
+
+    /// The node right behind this one.
+    pub fn next(&self) -> *mut AllocList {
+        (self as *const AllocList as usize + self.size()) as *mut AllocList
+    }
+}
+
+/// Size of the node for an allocation of `size` bytes, including the header.
+/// Allocations are rounded up to 8 bytes.
+pub fn aligned_size(size: usize) -> usize {
+    align_up(size, 8) + HEADER_SIZE
+}
+
+/// Bytes a free node needs in addition to `aligned_size` to be sure that an
+/// allocation aligned to `align` fits into it.
+/// The bytes skipped for the alignment stay behind as a free node.
+pub fn align_padding(align: usize) -> usize {
+    if align > 8 { align + HEADER_SIZE } else { 0 }
+}
+
+/// The node of an allocation.
+/// # Safety
+/// `ptr` must have been returned by [`alloc`].
+pub unsafe fn header(ptr: *mut u8) -> *mut AllocList {
+    (ptr as *mut AllocList).offset(-1)
+}
+
+/// Iterator over the nodes of a list, see [`nodes`].
+pub struct Nodes {
+    head: *mut AllocList,
+    tail: *mut AllocList,
+}
+
+impl Iterator for Nodes {
+    type Item = *mut AllocList;
+
+    fn next(&mut self) -> Option<*mut AllocList> {
+        // A node of size 0 means the list is broken, e.g. by a double free.
+        if self.head >= self.tail || unsafe { (*self.head).size() } == 0 {
+            return None;
+        }
+
+        let node = self.head;
+        self.head = unsafe { (*node).next() };
+        Some(node)
+    }
+}
+
+/// Iterate over the nodes from `head` up to `tail`.
+/// # Safety
+/// `head..tail` must be an allocation list.
+pub unsafe fn nodes(head: *mut AllocList, tail: *mut AllocList) -> Nodes {
+    Nodes { head, tail }
+}
+
+/// Allocates `aligned_size` bytes, including the header, from the list `head..tail`.
+/// The memory after the header is aligned to `align` bytes,
+/// which must be a power of two of at least 8.
+/// Returns None if no free node is large enough.
+/// # Safety
+/// `head..tail` must be an allocation list.
+pub unsafe fn alloc(head: *mut AllocList, tail: *mut AllocList, aligned_size: usize, align: usize) -> Option<*mut u8> {
+    debug_assert!(align.is_power_of_two() && align >= 8);
+
+    for head in nodes(head, tail) {
+        if (*head).is_taken() {
+            continue;
+        }
+
+        let chunk_size = (*head).size();
+
+        // The header of the allocation sits right before the aligned memory.
+        // Skipped bytes stay behind as a free node, which needs room for its header.
+        let mut node = align_up(head.add(1) as usize, align) - HEADER_SIZE;
+        if node != head as usize && node - (head as usize) <= HEADER_SIZE {
+            node += align;
+        }
+        let padding = node - head as usize;
+
+        if padding + aligned_size <= chunk_size {
+            let node = node as *mut AllocList;
+            if padding > 0 {
+                (*head).set_size(padding);
+                AllocList::init(node, chunk_size - padding);
+            }
+
+            take(node, aligned_size);
+            return Some(node.add(1) as *mut u8);
+        }
+    }
+
+    None
+}
+
+/// Marks a free node as taken and splits off what is not needed of it.
+/// # Safety
+/// `head` must be a free node of at least `aligned_size` bytes.
+pub unsafe fn take(head: *mut AllocList, aligned_size: usize) {
+    let chunk_size = (*head).size();
+    let remaining_size = chunk_size - aligned_size;
+    (*head).set_taken();
+
+    if remaining_size > HEADER_SIZE {
+        let new_head = (head as *mut u8).add(aligned_size) as *mut AllocList;
+        AllocList::init(new_head, remaining_size);
+        (*head).set_size(aligned_size);
+    } else {
+        (*head).set_size(chunk_size);
+    }
+}
+
+/// Resizes the taken node `head` to `aligned_size` bytes without moving it.
+/// Growing only works if the node after it is free and large enough.
+/// Returns false if the allocation has to be moved instead.
+/// # Safety
+/// `head` must be a taken node of the list ending at `tail`.
+/// The list has to be coalesced afterwards.
+pub unsafe fn resize(head: *mut AllocList, tail: *mut AllocList, aligned_size: usize) -> bool {
+    // Take the following free node into the allocation, then give back what is not needed.
+    let next = (*head).next();
+    let available = if next < tail && (*next).is_free() {
+        (*head).size() + (*next).size()
+    } else {
+        (*head).size()
+    };
+
+    if aligned_size > available {
+        return false;
+    }
+
+    (*head).set_free();
+    (*head).set_size(available);
+    take(head, aligned_size);
+    true
+}
+
+/// Coalesces (Merges) adjacent free nodes of the list `head..tail`.
+/// This function is called after a block of memory is freed to reduce fragmentation.
+/// # Safety
+/// `head..tail` must be an allocation list.
+pub unsafe fn coalesce(mut head: *mut AllocList, tail: *mut AllocList) {
+    while head < tail {
+        let next = (*head).next();
+
+        if (*head).size() == 0 {
+            // If the size of the current node is 0, we have
+            // a bad heap cause by a double free (I think).
+            break;
+        } else if next >= tail {
+            // If the next node is out of bounds, we're done.
+            break;
+        } else if (*head).is_free() && (*next).is_free() {
+            // If the current node and the next node are free,
+            // we can merge them into one node.
+            // The merged node may be followed by another free one.
+            (*head).set_size((*head).size() + (*next).size());
+            continue;
+        }
+
+        head = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::alloc::Layout;
+    use core::ptr;
+    use crate::test_utils::{random_workload, Arena};
+
+    /// An allocation list over a whole arena, used the way `kmalloc` uses it.
+    struct List {
+        head: *mut AllocList,
+        tail: *mut AllocList,
+    }
+
+    impl List {
+        fn new(arena: &Arena) -> Self {
+            let head = arena.start() as *mut AllocList;
+            unsafe { AllocList::init(head, arena.size()) };
+            Self { head, tail: arena.end() as *mut AllocList }
+        }
+
+        fn alloc(&mut self, layout: Layout) -> *mut u8 {
+            let align = layout.align().max(8);
+            unsafe { alloc(self.head, self.tail, aligned_size(layout.size()), align) }.unwrap_or(ptr::null_mut())
+        }
+
+        fn dealloc(&mut self, ptr: *mut u8, _layout: Layout) {
+            unsafe {
+                let node = header(ptr);
+                assert!((*node).is_taken());
+                (*node).set_free();
+                coalesce(self.head, self.tail);
+            }
+            self.assert_valid();
+        }
+
+        /// Checks that the nodes split the whole arena and no free nodes are left unmerged.
+        fn assert_valid(&self) {
+            let mut end = self.head as usize;
+            let mut previous_free = false;
+            for node in unsafe { nodes(self.head, self.tail) } {
+                let node = unsafe { *node };
+                assert_eq!(node.size() % 8, 0);
+                assert!(node.size() > HEADER_SIZE || node.is_free());
+                assert!(!(previous_free && node.is_free()), "free nodes were not coalesced");
+                previous_free = node.is_free();
+                end += node.size();
+            }
+            assert_eq!(end, self.tail as usize, "the nodes do not cover the list");
+        }
+
+        fn free_nodes(&self) -> Vec<usize> {
+            unsafe { nodes(self.head, self.tail) }
+                .filter(|&node| unsafe { (*node).is_free() })
+                .map(|node| unsafe { (*node).size() })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn random_allocations() {
+        let arena = Arena::new(1024 * 1024);
+        let mut list = List::new(&arena);
+
+        for seed in 1..=8 {
+            let failed = random_workload(&arena, seed, 2048, 1024, &mut list, List::alloc, List::dealloc);
+            assert_eq!(failed, 0);
+            assert_eq!(list.free_nodes(), [arena.size()]);
+        }
+    }
+
+    #[test]
+    fn alignment_padding_stays_free() {
+        let arena = Arena::new(4096);
+        let mut list = List::new(&arena);
+
+        let ptr = list.alloc(Layout::from_size_align(64, 256).unwrap());
+        assert_eq!(ptr as usize, arena.start() + 256);
+        assert_eq!(list.free_nodes(), [256 - HEADER_SIZE, 4096 - 256 - 64]);
+
+        // The padding is large enough for small allocations.
+        let small = list.alloc(Layout::from_size_align(16, 8).unwrap());
+        assert_eq!(small as usize, arena.start() + HEADER_SIZE);
+        list.assert_valid();
+    }
+
+    #[test]
+    fn fails_when_full() {
+        let arena = Arena::new(4096);
+        let mut list = List::new(&arena);
+
+        let layout = Layout::from_size_align(4096 - HEADER_SIZE, 8).unwrap();
+        let ptr = list.alloc(layout);
+        assert!(!ptr.is_null());
+        assert!(list.alloc(Layout::new::<u8>()).is_null());
+
+        list.dealloc(ptr, layout);
+        assert_eq!(list.free_nodes(), [4096]);
+    }
+
+    #[test]
+    fn resize_in_place() {
+        let arena = Arena::new(4096);
+        let mut list = List::new(&arena);
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let first = list.alloc(layout);
+        let second = list.alloc(layout);
+        let third = list.alloc(layout);
+        list.dealloc(second, layout);
+
+        unsafe {
+            // Grows into the free node behind it, but not past the next allocation.
+            assert!(!resize(header(first), list.tail, aligned_size(64 * 2 + HEADER_SIZE + 8)));
+            assert!(resize(header(first), list.tail, aligned_size(100)));
+            coalesce(list.head, list.tail);
+            list.assert_valid();
+            assert_eq!((*header(first)).size(), aligned_size(100));
+
+            // Shrinking gives the rest back.
+            assert!(resize(header(first), list.tail, aligned_size(8)));
+            coalesce(list.head, list.tail);
+            list.assert_valid();
+            assert_eq!(list.free_nodes()[0], 2 * aligned_size(64) - aligned_size(8));
+        }
+
+        list.dealloc(first, layout);
+        list.dealloc(third, layout);
+        assert_eq!(list.free_nodes(), [4096]);
+    }
+
+    #[test]
+    fn flags_keep_size() {
+        let mut node = AllocList { flags_size: 0 };
+        node.set_size(4096);
+        node.set_taken();
+        assert_eq!(node.size(), 4096);
+        node.set_size(64);
+        assert!(node.is_taken());
+        node.set_free();
+        assert_eq!(node.size(), 64);
+    }
+}
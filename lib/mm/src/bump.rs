@@ -0,0 +1,99 @@
+use core::alloc::Layout;
+use core::ptr;
+use crate::align_up;
+
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl Default for BumpAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        Self {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+
+    /// Initializes the bump memory with the given heap bounds.
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that
+    /// the given heap bounds are valid and that the memory in this range is unused.
+    /// This method must be called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    /// Allocates memory for `layout` behind the previous allocation.
+    /// Returns null if the heap is exhausted.
+    pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return ptr::null_mut(),
+        };
+
+        if alloc_end > self.heap_end {
+            ptr::null_mut() // out of memory
+        } else {
+            self.next = alloc_end;
+            self.allocations += 1;
+            alloc_start as *mut u8
+        }
+    }
+
+    /// Frees an allocation. The memory is only reused
+    /// once every allocation has been freed.
+    pub fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{random_workload, Arena};
+
+    #[test]
+    fn allocations_are_aligned_and_in_bounds() {
+        let arena = Arena::new(64 * 1024);
+        let mut allocator = BumpAllocator::new();
+        unsafe { allocator.init(arena.start(), arena.size()) };
+
+        // The heap never reuses memory before it is empty, so some allocations fail.
+        random_workload(&arena, 1, 256, 64, &mut allocator, BumpAllocator::alloc, BumpAllocator::dealloc);
+        assert_eq!(allocator.allocations, 0);
+        assert_eq!(allocator.next, arena.start());
+    }
+
+    #[test]
+    fn fails_when_exhausted() {
+        let arena = Arena::new(4096);
+        let mut allocator = BumpAllocator::new();
+        unsafe { allocator.init(arena.start(), arena.size()) };
+
+        let page = Layout::from_size_align(4096, 8).unwrap();
+        let ptr = allocator.alloc(page);
+        assert_eq!(ptr as usize, arena.start());
+        assert!(allocator.alloc(Layout::new::<u8>()).is_null());
+
+        // Freeing the only allocation makes the whole heap available again.
+        allocator.dealloc(ptr, page);
+        assert_eq!(allocator.alloc(page), ptr);
+    }
+}
@@ -0,0 +1,202 @@
+use core::alloc::Layout;
+use core::mem;
+use crate::LinkedListAllocator;
+
+/// Available block sizes.
+/// The block sizes must all be a power of 2 and must be in ascending order
+/// because they are also used for block alignment (alignments must be
+/// always powers of 2).
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// Serves small allocations from lists of equally sized free blocks.
+/// Blocks and larger allocations come from a linked list allocator.
+pub struct FixedSizedBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    /// Blocks of every size handed out and not yet freed.
+    in_use: [usize; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+}
+
+impl Default for FixedSizedBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedSizedBlockAllocator {
+    /// Creates an empty FixedSizeBlockAllocator.
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        Self {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            in_use: [0; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+        }
+    }
+
+    /// Initialize the memory with the given heap bounds.
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    /// Allocates memory for `layout`. Returns null if the heap is exhausted.
+    /// # Safety
+    /// The allocator must have been initialized with [`FixedSizedBlockAllocator::init`].
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        match block_size_list_index(&layout) {
+            Some(index) => {
+                let ptr = match self.list_heads[index].take() {
+                    Some(node) => {
+                        self.list_heads[index] = node.next.take();
+                        node as *mut ListNode as *mut u8
+                    },
+                    None => {
+                        // No block exists in the list yet.
+                        // We must thus allocate a new one.
+                        self.fallback_alloc(block_layout(index))
+                    }
+                };
+
+                if !ptr.is_null() {
+                    self.in_use[index] += 1;
+                }
+                ptr
+            }
+            None => self.fallback_alloc(layout),
+        }
+    }
+
+    /// Frees memory allocated with the same `layout`.
+    /// # Safety
+    /// `ptr` must have been returned by [`FixedSizedBlockAllocator::alloc`] for `layout`.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        match block_size_list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: self.list_heads[index].take(),
+                };
+
+                // verify that block has size and alignment required for storing node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                self.list_heads[index] = Some(&mut *new_node_ptr);
+
+                // Nothing of this size is in use anymore, return the blocks.
+                self.in_use[index] -= 1;
+                if self.in_use[index] == 0 {
+                    self.release_blocks(index);
+                }
+            }
+            None => self.fallback_dealloc(ptr, layout)
+        }
+    }
+
+    unsafe fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_allocator.alloc(layout)
+    }
+
+    unsafe fn fallback_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.fallback_allocator.dealloc(ptr, layout)
+    }
+
+    /// Gives all free blocks of a size back to the fallback allocator,
+    /// so their memory can be merged and used for other sizes.
+    unsafe fn release_blocks(&mut self, index: usize) {
+        while let Some(node) = self.list_heads[index].take() {
+            self.list_heads[index] = node.next.take();
+            self.fallback_dealloc(node as *mut ListNode as *mut u8, block_layout(index));
+        }
+    }
+}
+
+/// Layout of the blocks in the list at `index`.
+fn block_layout(index: usize) -> Layout {
+    let block_size = BLOCK_SIZES[index];
+    // This only applies if block size is a power of 2.
+    let block_align = block_size;
+    Layout::from_size_align(block_size, block_align).unwrap()
+}
+
+/// Choose an appropriate block size for the given layout.
+///
+/// Returns an index into the `BLOCK_SIZES` array.
+fn block_size_list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{random_workload, Arena};
+
+    fn allocator(arena: &Arena) -> FixedSizedBlockAllocator {
+        let mut allocator = FixedSizedBlockAllocator::new();
+        unsafe { allocator.init(arena.start(), arena.size()) };
+        allocator
+    }
+
+    #[test]
+    fn random_allocations() {
+        let arena = Arena::new(2 * 1024 * 1024);
+        let mut allocator = allocator(&arena);
+
+        for seed in 1..=8 {
+            // Sizes span the block lists and the fallback allocator.
+            let failed = random_workload(
+                &arena,
+                seed,
+                4096,
+                256,
+                &mut allocator,
+                |allocator, layout| unsafe { allocator.alloc(layout) },
+                |allocator, ptr, layout| unsafe { allocator.dealloc(ptr, layout) },
+            );
+            assert_eq!(failed, 0);
+            assert_eq!(allocator.in_use, [0; BLOCK_SIZES.len()]);
+            assert!(allocator.list_heads.iter().all(Option::is_none));
+        }
+
+        // All blocks went back to the fallback allocator, the whole heap is one block again.
+        let layout = Layout::from_size_align(arena.size(), 8).unwrap();
+        assert_eq!(unsafe { allocator.alloc(layout) } as usize, arena.start());
+    }
+
+    #[test]
+    fn freed_blocks_are_reused() {
+        let arena = Arena::new(4096);
+        let mut allocator = allocator(&arena);
+
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let keep = unsafe { allocator.alloc(layout) };
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert!(allocator.list_heads[block_size_list_index(&layout).unwrap()].is_some());
+        assert_eq!(unsafe { allocator.alloc(layout) }, ptr);
+        assert_ne!(keep, ptr);
+    }
+
+    #[test]
+    fn block_size_fits_layout() {
+        for size in 1..=2048 {
+            for align in [1, 2, 4, 8, 16, 64, 512] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let index = block_size_list_index(&layout).unwrap();
+                assert!(BLOCK_SIZES[index] >= size && BLOCK_SIZES[index] >= align);
+                assert!(index == 0 || BLOCK_SIZES[index - 1] < size.max(align));
+            }
+        }
+        assert_eq!(block_size_list_index(&Layout::from_size_align(2049, 8).unwrap()), None);
+    }
+}
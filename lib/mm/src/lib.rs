@@ -0,0 +1,39 @@
+//! Memory management of the kernel that does not depend on the hardware.
+//!
+//! The allocators and the Sv39 page table code only do pointer arithmetic
+//! on memory they are handed, so they are built for the host as well
+//! and tested there. Run the tests with `cargo test-host`.
+#![cfg_attr(not(test), no_std)]
+
+pub mod alloc_list;
+pub mod bump;
+pub mod fixed_sized_block;
+pub mod linked_list;
+pub mod sv39;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use bump::BumpAllocator;
+pub use fixed_sized_block::FixedSizedBlockAllocator;
+pub use linked_list::LinkedListAllocator;
+
+/// Align the address `addr` upwards to alignment `align`.
+/// # Safety
+/// `align` must be a power of two.
+/// # Logic
+/// The following is a more readable version of the align_up function.
+/// ```
+/// # let (addr, align) = (13, 8);
+/// let remainder = addr % align;
+/// let aligned = if remainder == 0 {
+///     addr // addr already aligned
+/// } else {
+///     addr - remainder + align
+/// };
+/// # assert_eq!(aligned, mm::align_up(addr, align));
+/// ```
+pub fn align_up(addr: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two());
+    (addr + align - 1) & !(align - 1)
+}
@@ -0,0 +1,264 @@
+use core::alloc::Layout;
+use core::mem;
+use core::ptr;
+use crate::align_up;
+
+/// Header written into every free block.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+impl FreeBlock {
+    fn start(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end(&self) -> usize {
+        self.start() + self.size
+    }
+}
+
+/// Allocator that keeps the free memory in a list of blocks, sorted by address.
+///
+/// Allocations take the first block they fit into. Freed memory is merged
+/// with the blocks right before and after it, so the heap does not fragment
+/// into pieces that are too small for the next allocation.
+pub struct LinkedListAllocator {
+    head: *mut FreeBlock,
+}
+
+// The free blocks are owned by the allocator.
+unsafe impl Send for LinkedListAllocator {}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkedListAllocator {
+    /// Smallest block the allocator hands out or keeps track of.
+    pub const MIN_BLOCK_SIZE: usize = mem::size_of::<FreeBlock>();
+
+    pub const fn new() -> Self {
+        Self { head: ptr::null_mut() }
+    }
+
+    /// Initializes the allocator with the given heap bounds.
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that
+    /// the given heap bounds are valid and that the memory in this range is unused.
+    /// This method must be called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    /// Size and alignment of the block used for an allocation,
+    /// large enough to hold the header once it is freed.
+    fn block_layout(layout: Layout) -> (usize, usize) {
+        let align = layout.align().max(mem::align_of::<FreeBlock>());
+        let size = align_up(layout.size().max(Self::MIN_BLOCK_SIZE), mem::align_of::<FreeBlock>());
+        (size, align)
+    }
+
+    /// Allocates memory for `layout`. Returns null if no free block is large enough.
+    /// # Safety
+    /// The allocator must have been initialized with [`LinkedListAllocator::init`].
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::block_layout(layout);
+
+        let mut previous: *mut FreeBlock = ptr::null_mut();
+        let mut current = self.head;
+        while !current.is_null() {
+            if let Some(start) = Self::fit(&*current, size, align) {
+                let block_start = (*current).start();
+                let block_end = (*current).end();
+                let next = (*current).next;
+
+                // Unlink the block, then give back what is left in front and behind.
+                if previous.is_null() {
+                    self.head = next;
+                } else {
+                    (*previous).next = next;
+                }
+
+                if start > block_start {
+                    self.add_free_region(block_start, start - block_start);
+                }
+                if block_end > start + size {
+                    self.add_free_region(start + size, block_end - start - size);
+                }
+
+                return start as *mut u8;
+            }
+
+            previous = current;
+            current = (*current).next;
+        }
+
+        ptr::null_mut()
+    }
+
+    /// Frees memory allocated with the same `layout`.
+    /// # Safety
+    /// `ptr` must have been returned by [`LinkedListAllocator::alloc`] for `layout`.
+    /// This function will panic if the memory is already free.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_layout(layout);
+        self.add_free_region(ptr as usize, size);
+    }
+
+    /// Returns the start of the allocation if `size` bytes aligned to `align` fit into the block.
+    /// Leftovers in front or behind the allocation have to be large enough to be tracked.
+    fn fit(block: &FreeBlock, size: usize, align: usize) -> Option<usize> {
+        let mut start = align_up(block.start(), align);
+        if start != block.start() && start - block.start() < Self::MIN_BLOCK_SIZE {
+            start = align_up(block.start() + Self::MIN_BLOCK_SIZE, align);
+        }
+
+        let end = start.checked_add(size)?;
+        if end > block.end() {
+            return None;
+        }
+
+        let excess = block.end() - end;
+        if excess > 0 && excess < Self::MIN_BLOCK_SIZE {
+            return None;
+        }
+
+        Some(start)
+    }
+
+    /// Inserts a free region at its place in the address ordered list
+    /// and merges it with its neighbours.
+    unsafe fn add_free_region(&mut self, start: usize, size: usize) {
+        assert_eq!(align_up(start, mem::align_of::<FreeBlock>()), start);
+        assert!(size >= Self::MIN_BLOCK_SIZE);
+
+        let mut previous: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (*next).start() < start {
+            previous = next;
+            next = (*next).next;
+        }
+
+        assert!(next.is_null() || start + size <= (*next).start(), "possible double-free detected");
+        assert!(previous.is_null() || (*previous).end() <= start, "possible double-free detected");
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+
+        if !next.is_null() && (*block).end() == (*next).start() {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if previous.is_null() {
+            self.head = block;
+        } else if (*previous).end() == start {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        } else {
+            (*previous).next = block;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{random_workload, Arena};
+
+    fn allocator(arena: &Arena) -> LinkedListAllocator {
+        let mut allocator = LinkedListAllocator::new();
+        unsafe { allocator.init(arena.start(), arena.size()) };
+        allocator
+    }
+
+    /// Checks that the free list is sorted, merged and spans exactly `bytes`.
+    fn assert_free(allocator: &LinkedListAllocator, bytes: usize) {
+        let mut free = 0;
+        let mut block = allocator.head;
+        while !block.is_null() {
+            let next = unsafe { (*block).next };
+            unsafe {
+                assert!((*block).size >= LinkedListAllocator::MIN_BLOCK_SIZE);
+                assert!(next.is_null() || (*block).end() < (*next).start(), "free blocks are not sorted and merged");
+                free += (*block).size;
+            }
+            block = next;
+        }
+        assert_eq!(free, bytes);
+    }
+
+    #[test]
+    fn random_allocations() {
+        let arena = Arena::new(256 * 1024);
+        let mut allocator = allocator(&arena);
+
+        for seed in 1..=8 {
+            let failed = random_workload(
+                &arena,
+                seed,
+                1024,
+                512,
+                &mut allocator,
+                |allocator, layout| unsafe { allocator.alloc(layout) },
+                |allocator, ptr, layout| unsafe { allocator.dealloc(ptr, layout) },
+            );
+            assert_eq!(failed, 0);
+
+            // Everything merged back into a single block.
+            assert_free(&allocator, arena.size());
+            assert_eq!(allocator.head as usize, arena.start());
+        }
+    }
+
+    #[test]
+    fn fills_the_heap() {
+        let arena = Arena::new(4096);
+        let mut allocator = allocator(&arena);
+
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr as usize, arena.start());
+        assert!(allocator.head.is_null());
+        assert!(unsafe { allocator.alloc(Layout::new::<u8>()) }.is_null());
+
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_free(&allocator, arena.size());
+    }
+
+    #[test]
+    fn alignment_padding_is_reused() {
+        let arena = Arena::new(4096);
+        let mut allocator = allocator(&arena);
+
+        // The bytes skipped in front of the aligned allocation stay free.
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let aligned = Layout::from_size_align(64, 1024).unwrap();
+        let first = unsafe { allocator.alloc(small) };
+        let second = unsafe { allocator.alloc(aligned) };
+        assert_eq!(second as usize, arena.start() + 1024);
+
+        let third = unsafe { allocator.alloc(small) };
+        assert_eq!(third as usize, first as usize + 16);
+        assert_free(&allocator, arena.size() - 16 - 64 - 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "double-free")]
+    fn double_free_panics() {
+        let arena = Arena::new(4096);
+        let mut allocator = allocator(&arena);
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe {
+            allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+}
@@ -22,7 +22,7 @@ pub enum EntryBits {
 }
 
 impl EntryBits {
-    pub(crate) fn bits(self) -> i64 {
+    pub fn bits(self) -> i64 {
         self as i64
     }
 }
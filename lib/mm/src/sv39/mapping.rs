@@ -0,0 +1,341 @@
+use crate::align_up;
+use crate::sv39::{Entry, EntryBits, Table, PAGE_SIZE};
+
+/// Provides the pages for the tables below the root table.
+pub trait FrameAllocator {
+    /// Allocates a zeroed page, aligned to [`PAGE_SIZE`].
+    /// Returns None if there is no memory left.
+    fn zalloc(&mut self) -> Option<*mut u8>;
+
+    /// Frees a page returned by [`FrameAllocator::zalloc`].
+    fn dealloc(&mut self, frame: *mut u8);
+}
+
+/// Map a virtual address to a physical address.
+/// Missing tables are allocated from `frames`.
+/// # Safety
+/// This function will fail if the provided level is greater than 2
+/// or if `frames` is out of memory.
+///
+/// ## Flags
+/// The provided flags are what will be set in the entry.
+/// The flags can be a combination of the following:
+/// - [`EntryBits::Read`]
+/// - [`EntryBits::Write`]
+/// - [`EntryBits::Execute`]
+/// - [`EntryBits::User`]
+/// - [`EntryBits::Global`]
+///
+/// The flags must have at least one of the R, W, or X bits set.
+/// The Valid bit is set automatically.
+pub fn map(root: &mut Table, vaddr: usize, paddr: usize, flags: i64, level: usize, frames: &mut impl FrameAllocator) {
+    // Make sure that either the R, W, or X bit is set.
+    assert_ne!(flags & EntryBits::ReadWriteExecute.bits(), 0);
+    assert!(level < 3);
+
+    // Extract the VPN (Virtual Page Number) from the virtual address.
+    // Each VPN is 9 bits long. Thus, we use 0x1ff (0b1_1111_1111) to mask.
+    let vpn = [
+        // VPN[0] = vaddr[20:12]
+        (vaddr >> 12) & 0x1ff,
+        // VPN[1] = vaddr[29:21]
+        (vaddr >> 21) & 0x1ff,
+        // VPN[2] = vaddr[38:30]
+        (vaddr >> 30) & 0x1ff,
+    ];
+
+    // Extract the PPN (Physical Page Number) from the physical address.
+    // Each PPN except for the last one is 9 bits long. Thus, we use 0x1ff (0b1_1111_1111) to mask.
+    // The last PPN is 26 bits long. Thus, we use 0x3ff_ffff (0b11_1111_1111_1111_1111_1111_1111) to mask.
+    let ppn = [
+        // PPN[0] = paddr[20:12]
+        (paddr >> 12) & 0x1ff,
+        // PPN[1] = paddr[29:21]
+        (paddr >> 21) & 0x1ff,
+        // PPN[2] = paddr[55:30]
+        (paddr >> 30) & 0x3ff_ffff,
+    ];
+
+    let mut v = &mut root.entries[vpn[2]];
+
+    // Now, we're going to traverse the page table and set the bits
+    // properly. We expect the root to be valid, however we're required to
+    // create anything beyond the root.
+    // In Rust, we create a range iterator using the .. operator.
+    // The .rev() will reverse the iteration since we need to start with
+    // VPN[2] The .. operator is inclusive on start but exclusive on end.
+    // So, (0..2) will iterate 0 and 1.
+    for i in (level..2).rev() {
+        if v.is_invalid() {
+            let page = frames.zalloc().expect("out of memory");
+            // The page is already aligned by 4,096, so store it
+            // directly The page is stored in the entry shifted
+            // right by 2 places.
+            v.set((page as i64 >> 2) | EntryBits::Valid.bits());
+        }
+
+        let entry = ((v.get() & !0x3ff) << 2) as *mut Entry;
+        v = unsafe { entry.add(vpn[i]).as_mut().expect("entry is null") };
+    }
+
+    let entry =
+        (ppn[2] << 28) as i64 | // PPN[2] = [53:28]
+            (ppn[1] << 19) as i64 | // PPN[1] = [27:19]
+            (ppn[0] << 10) as i64 | // PPN[0] = [18:10]
+            flags |                 // Specified flags such as R, W, X, U, G
+            EntryBits::Valid.bits();// Valid bit
+    v.set(entry);
+}
+
+/// Unmaps a table and gives the tables below it back to `frames`.
+/// Note that root itself is not deallocated.
+pub fn unmap(root: &mut Table, frames: &mut impl FrameAllocator) {
+    // Start at level 2
+    for entry_lv2 in root.entries.iter() {
+        if entry_lv2.is_valid() && entry_lv2.is_branch() {
+            let memaddr_lv1 = (entry_lv2.get() & !0x3ff) << 2;
+            let table_lv1 = unsafe {
+                (memaddr_lv1 as *mut Table).as_mut().expect("table_lv1 is null")
+            };
+
+            for entry_lv1 in table_lv1.entries.iter() {
+                if entry_lv1.is_valid() && entry_lv1.is_branch() {
+                    let memaddr_lv0 = (entry_lv1.get() & !0x3ff) << 2;
+                    // The next level is level 0, which
+                    // cannot have branches, therefore,
+                    // we free here.
+                    frames.dealloc(memaddr_lv0 as *mut u8);
+                }
+            }
+
+            frames.dealloc(memaddr_lv1 as *mut u8);
+        }
+    }
+}
+
+/// Translate a virtual address to a physical address.
+/// Walk the page table to convert a virtual address to a
+/// physical address.
+/// If a page fault would occur, this returns None
+/// Otherwise, it returns Some with the physical address.
+pub fn virt_to_phys(root: &Table, vaddr: usize) -> Option<usize> {
+    // Extract the VPN (Virtual Page Number) from the virtual address.
+    // Each VPN is 9 bits long. Thus, we use 0x1ff (0b1_1111_1111) to mask.
+    let vpn = [
+        // VPN[0] = vaddr[20:12]
+        (vaddr >> 12) & 0x1ff,
+        // VPN[1] = vaddr[29:21]
+        (vaddr >> 21) & 0x1ff,
+        // VPN[2] = vaddr[38:30]
+        (vaddr >> 30) & 0x1ff,
+    ];
+
+    let mut v = &root.entries[vpn[2]];
+
+    for i in (0..=2).rev() {
+        if v.is_invalid() {
+            break;
+        }
+        else if v.is_leaf() {
+            let off_mask = (1 << (12 + 9 * i)) - 1;
+            let vaddr_pgoff = vaddr & off_mask;
+            let addr = ((v.get() << 2) as usize) & !off_mask;
+            return Some(addr | vaddr_pgoff);
+        }
+
+        // Set v to the next entry which is pointed to by
+        // this entry. However, we the address is shifted right
+        // by 2 bits when stored in the page table entry. So
+        // we need to shift left by 2 bits to get the usable address.
+        let entry = ((v.get() & !0x3ff) << 2) as *mut Entry;
+        v = unsafe { entry.add(vpn[i - 1]).as_ref().expect("entry is null") };
+    }
+
+    None
+}
+
+
+/// Identity map range
+/// Takes a contiguous allocation of memory and maps it using PAGE_SIZE
+/// This assumes that start <= end
+pub fn id_map_range(
+    root: &mut Table,
+    start: usize,
+    end: usize,
+    bits: i64,
+    frames: &mut impl FrameAllocator,
+) {
+    assert!(start <= end, "start must be less than or equal to end");
+
+    let mut memaddr = start & !(PAGE_SIZE - 1);
+    let num_kb_pages = (align_up(end, PAGE_SIZE)
+        - memaddr)
+        / PAGE_SIZE;
+
+    // I named this num_kb_pages for future expansion when
+    // I decide to allow for GiB (2^30) and 2MiB (2^21) page
+    // sizes. However, the overlapping memory regions are causing
+    // nightmares.
+    for _ in 0..num_kb_pages {
+        map(root, memaddr, memaddr, bits, 0, frames);
+        memaddr += 1 << 12;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::test_utils::{Arena, Rng};
+
+    const FLAGS: [EntryBits; 4] = [
+        EntryBits::Read,
+        EntryBits::ReadWrite,
+        EntryBits::ReadExecute,
+        EntryBits::UserReadWrite,
+    ];
+
+    /// Hands out the pages of an arena and counts what is given back.
+    struct Frames {
+        arena: Arena,
+        next: usize,
+        allocated: usize,
+        freed: Vec<usize>,
+    }
+
+    impl Frames {
+        fn new(pages: usize) -> Self {
+            let arena = Arena::new(pages * PAGE_SIZE);
+            Self { next: arena.start(), arena, allocated: 0, freed: Vec::new() }
+        }
+
+        fn root(&mut self) -> &'static mut Table {
+            let root = self.zalloc().unwrap() as *mut Table;
+            self.allocated = 0;
+            unsafe { &mut *root }
+        }
+    }
+
+    impl FrameAllocator for Frames {
+        fn zalloc(&mut self) -> Option<*mut u8> {
+            if self.next == self.arena.end() {
+                return None;
+            }
+
+            let frame = self.next;
+            self.next += PAGE_SIZE;
+            self.allocated += 1;
+            Some(frame as *mut u8)
+        }
+
+        fn dealloc(&mut self, frame: *mut u8) {
+            let frame = frame as usize;
+            assert!(frame >= self.arena.start() && frame < self.next);
+            assert_eq!(frame % PAGE_SIZE, 0);
+            assert!(!self.freed.contains(&frame), "table freed twice");
+            self.freed.push(frame);
+        }
+    }
+
+    #[test]
+    fn map_translate_round_trip() {
+        let mut frames = Frames::new(2048);
+        let root = frames.root();
+        let mut rng = Rng::new(7);
+        let mut mapped = HashMap::new();
+
+        // The pages are spread over the lowest 2 GiB, so the tables fit into the frames.
+        for _ in 0..4000 {
+            let vaddr = rng.below(1 << 19) * PAGE_SIZE;
+            let paddr = rng.below(1 << 44) * PAGE_SIZE;
+            let flags = FLAGS[rng.below(FLAGS.len())].bits();
+            map(root, vaddr, paddr, flags, 0, &mut frames);
+            mapped.insert(vaddr, paddr);
+        }
+
+        for (&vaddr, &paddr) in &mapped {
+            let offset = rng.below(PAGE_SIZE);
+            assert_eq!(virt_to_phys(root, vaddr + offset), Some(paddr + offset));
+        }
+
+        for _ in 0..4000 {
+            let vaddr = rng.below(1 << 39);
+            if !mapped.contains_key(&(vaddr & !(PAGE_SIZE - 1))) {
+                assert_eq!(virt_to_phys(root, vaddr), None);
+            }
+        }
+    }
+
+    #[test]
+    fn superpages() {
+        let mut frames = Frames::new(16);
+        let root = frames.root();
+        let mut rng = Rng::new(11);
+
+        // A gigapage needs no tables, a megapage one below the root.
+        let giga = 4 << 30;
+        let giga_paddr = 7 << 30;
+        map(root, giga, giga_paddr, EntryBits::ReadWrite.bits(), 2, &mut frames);
+        assert_eq!(frames.allocated, 0);
+
+        let mega = (3 << 30) + (5 << 21);
+        let mega_paddr = 9 << 21;
+        map(root, mega, mega_paddr, EntryBits::ReadExecute.bits(), 1, &mut frames);
+        assert_eq!(frames.allocated, 1);
+
+        for _ in 0..1000 {
+            let offset = rng.below(1 << 30);
+            assert_eq!(virt_to_phys(root, giga + offset), Some(giga_paddr + offset));
+
+            let offset = rng.below(1 << 21);
+            assert_eq!(virt_to_phys(root, mega + offset), Some(mega_paddr + offset));
+        }
+
+        assert_eq!(virt_to_phys(root, mega - 1), None);
+        assert_eq!(virt_to_phys(root, mega + (1 << 21)), None);
+        assert_eq!(virt_to_phys(root, giga + (1 << 30)), None);
+    }
+
+    #[test]
+    fn identity_map_range() {
+        let mut rng = Rng::new(13);
+
+        for _ in 0..32 {
+            let mut frames = Frames::new(16);
+            let root = frames.root();
+            let start = rng.below(1 << 38);
+            let end = start + rng.below(64 * PAGE_SIZE);
+            id_map_range(root, start, end, EntryBits::ReadWrite.bits(), &mut frames);
+
+            for addr in (start..end).step_by(97) {
+                assert_eq!(virt_to_phys(root, addr), Some(addr));
+            }
+            assert_eq!(virt_to_phys(root, start), Some(start));
+            assert_eq!(virt_to_phys(root, (start & !(PAGE_SIZE - 1)).wrapping_sub(1)), None);
+            assert_eq!(virt_to_phys(root, align_up(end.max(start + 1), PAGE_SIZE)), None);
+        }
+    }
+
+    #[test]
+    fn unmap_frees_all_tables() {
+        let mut frames = Frames::new(1024);
+        let root = frames.root();
+        let mut rng = Rng::new(17);
+
+        for _ in 0..500 {
+            let vaddr = rng.below(1 << 21) * PAGE_SIZE;
+            map(root, vaddr, vaddr, EntryBits::ReadWrite.bits(), 0, &mut frames);
+        }
+
+        unmap(root, &mut frames);
+        assert_eq!(frames.freed.len(), frames.allocated);
+    }
+
+    #[test]
+    #[should_panic]
+    fn map_requires_permissions() {
+        let mut frames = Frames::new(4);
+        let root = frames.root();
+        map(root, 0, 0, EntryBits::User.bits(), 0, &mut frames);
+    }
+}
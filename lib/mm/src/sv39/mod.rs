@@ -0,0 +1,11 @@
+//! Page tables of the Sv39 virtual memory system.
+pub mod entry;
+pub mod table;
+pub mod mapping;
+
+pub use table::*;
+pub use entry::*;
+pub use mapping::*;
+
+/// Size of a page and of a page table.
+pub const PAGE_SIZE: usize = 4096;
@@ -1,9 +1,10 @@
-use crate::arch::paging_sv39::Entry;
+use crate::sv39::Entry;
 
 pub struct Table {
     pub entries: [Entry; 512],
 }
 
+#[allow(clippy::len_without_is_empty)]
 impl Table {
     pub fn len(&self) -> usize {
         self.entries.len()
@@ -0,0 +1,150 @@
+//! Helpers for the host tests.
+use std::alloc::Layout;
+use crate::align_up;
+
+/// Operations of a [`random_workload`].
+const OPERATIONS: usize = 10_000;
+/// Most allocations alive at the same time in a [`random_workload`].
+const MAX_LIVE: usize = 256;
+
+/// Alignment of the start of an [`Arena`].
+const ARENA_ALIGN: usize = 4096;
+
+/// Memory for an allocator to manage, backed by a vector on the host.
+pub struct Arena {
+    _memory: Vec<u8>,
+    start: usize,
+    size: usize,
+}
+
+impl Arena {
+    /// Creates an arena of `size` bytes, its start is aligned to a page.
+    pub fn new(size: usize) -> Self {
+        let memory = vec![0u8; size + ARENA_ALIGN];
+        let start = align_up(memory.as_ptr() as usize, ARENA_ALIGN);
+        Self { _memory: memory, start, size }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+}
+
+/// Xorshift random numbers. Every run of a test sees the same sequence.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    pub fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    /// A random number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        self.next() % n
+    }
+
+    /// A random layout of up to `max_size` bytes, aligned to at most `max_align`.
+    pub fn layout(&mut self, max_size: usize, max_align: usize) -> Layout {
+        let size = 1 + self.below(max_size);
+        let align = 1 << self.below(max_align.trailing_zeros() as usize + 1);
+        Layout::from_size_align(size, align).unwrap()
+    }
+}
+
+/// The allocations a test holds.
+///
+/// Every allocation is filled with its own byte, which is checked when it is
+/// removed again. Overlapping allocations or an allocator that writes into
+/// handed out memory are caught that way.
+#[derive(Default)]
+pub struct Live {
+    allocations: Vec<(usize, Layout, u8)>,
+}
+
+impl Live {
+    pub fn len(&self) -> usize {
+        self.allocations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    /// Adds an allocation returned for `layout` from memory within `arena`.
+    pub fn insert(&mut self, arena: &Arena, ptr: *mut u8, layout: Layout) {
+        let start = ptr as usize;
+        let end = start + layout.size();
+        assert_eq!(start % layout.align(), 0, "allocation {:#x} is not aligned to {}", start, layout.align());
+        assert!(start >= arena.start() && end <= arena.end(), "allocation {:#x} is outside of the arena", start);
+
+        for &(other, other_layout, _) in &self.allocations {
+            assert!(end <= other || other + other_layout.size() <= start, "allocation {:#x} overlaps {:#x}", start, other);
+        }
+
+        let fill = self.allocations.len() as u8 ^ 0xa5;
+        unsafe { ptr.write_bytes(fill, layout.size()) };
+        self.allocations.push((start, layout, fill));
+    }
+
+    /// Removes the allocation at `index` and checks that its memory was left alone.
+    pub fn remove(&mut self, index: usize) -> (*mut u8, Layout) {
+        let (start, layout, fill) = self.allocations.swap_remove(index);
+        let memory = unsafe { std::slice::from_raw_parts(start as *const u8, layout.size()) };
+        assert!(memory.iter().all(|&byte| byte == fill), "allocation {:#x} was overwritten", start);
+        (start as *mut u8, layout)
+    }
+}
+
+/// Runs a random mix of allocations of up to `max_size` bytes, aligned to at most
+/// `max_align`, and frees on an allocator over `arena`. Everything is freed at the end.
+/// Returns the number of failed allocations.
+pub fn random_workload<A>(
+    arena: &Arena,
+    seed: u64,
+    max_size: usize,
+    max_align: usize,
+    allocator: &mut A,
+    alloc: impl Fn(&mut A, Layout) -> *mut u8,
+    dealloc: impl Fn(&mut A, *mut u8, Layout),
+) -> usize {
+    let mut rng = Rng::new(seed);
+    let mut live = Live::default();
+    let mut failed = 0;
+
+    for _ in 0..OPERATIONS {
+        if live.is_empty() || (rng.below(3) != 0 && live.len() < MAX_LIVE) {
+            let layout = rng.layout(max_size, max_align);
+            let ptr = alloc(allocator, layout);
+            if ptr.is_null() {
+                failed += 1;
+            } else {
+                live.insert(arena, ptr, layout);
+            }
+        } else {
+            let (ptr, layout) = live.remove(rng.below(live.len()));
+            dealloc(allocator, ptr, layout);
+        }
+    }
+
+    while !live.is_empty() {
+        let (ptr, layout) = live.remove(live.len() - 1);
+        dealloc(allocator, ptr, layout);
+    }
+
+    failed
+}
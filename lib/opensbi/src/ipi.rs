@@ -0,0 +1,20 @@
+// Documentation can be found here:
+// https://github.com/riscv-non-isa/riscv-sbi-doc/blob/master/src/ext-ipi.adoc
+
+use crate::{ecall2, SbiRet};
+
+pub const IPI_EID: usize = 0x735049;
+pub const IPI_EID_SEND_IPI_FID: usize = 0;
+
+/// Send a supervisor software interrupt to all harts in the mask.
+/// Bit `n` of `hart_mask` selects hart `hart_mask_base + n`.
+/// A `hart_mask_base` of `usize::MAX` selects all available harts.
+#[inline(always)]
+pub fn send_ipi(
+    hart_mask: usize,
+    hart_mask_base: usize,
+) -> SbiRet<usize> {
+    unsafe {
+        ecall2(IPI_EID, IPI_EID_SEND_IPI_FID, hart_mask, hart_mask_base)
+    }
+}
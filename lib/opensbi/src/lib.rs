@@ -3,11 +3,15 @@
 pub mod call;
 pub mod dbcn;
 pub mod hsm;
+pub mod ipi;
+pub mod srst;
 pub mod time;
 
 pub use call::*;
 pub use dbcn::*;
 pub use hsm::*;
+pub use ipi::*;
+pub use srst::*;
 
 /// Returns the address split up into (lo, hi)
 #[inline(always)]
@@ -0,0 +1,29 @@
+// Documentation can be found here:
+// https://github.com/riscv-non-isa/riscv-sbi-doc/blob/master/src/ext-sys-reset.adoc
+
+use crate::{ecall2, SbiRet};
+
+pub const SRST_EID: usize = 0x53525354;
+pub const SRST_EID_SYSTEM_RESET_FID: usize = 0;
+
+pub mod reset_type {
+    pub const SHUTDOWN: u32 = 0x00000000;
+    pub const COLD_REBOOT: u32 = 0x00000001;
+    pub const WARM_REBOOT: u32 = 0x00000002;
+}
+
+pub mod reset_reason {
+    pub const NO_REASON: u32 = 0x00000000;
+    pub const SYSTEM_FAILURE: u32 = 0x00000001;
+}
+
+/// Reset the system. Only returns if the reset failed.
+#[inline(always)]
+pub fn system_reset(
+    reset_type: u32,
+    reset_reason: u32,
+) -> SbiRet<usize> {
+    unsafe {
+        ecall2(SRST_EID, SRST_EID_SYSTEM_RESET_FID, reset_type as _, reset_reason as _)
+    }
+}
@@ -1,62 +1,26 @@
-use core::alloc::{GlobalAlloc, Layout};
-use core::ptr;
-use crate::allocator::align_up;
+use core::alloc::Layout;
+use crate::allocator::{heap_region, KernelHeap};
 use crate::allocator::lock::Locked;
 
-pub struct BumpAllocator {
-    heap_start: usize,
-    heap_end: usize,
-    next: usize,
-    allocations: usize,
-}
+pub use mm::BumpAllocator;
 
-impl BumpAllocator {
-    pub const fn new() -> Self {
-        Self {
-            heap_start: 0,
-            heap_end: 0,
-            next: 0,
-            allocations: 0,
-        }
+impl KernelHeap for Locked<BumpAllocator> {
+    fn name(&self) -> &'static str {
+        "bump"
     }
 
-    /// Initializes the bump memory with the given heap bounds.
-    /// # Safety
-    /// This function is unsafe because the caller must guarantee that
-    /// the given heap bounds are valid and that the memory in this range is unused.
-    /// This method must be called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.heap_start = heap_start;
-        self.heap_end = heap_start + heap_size;
-        self.next = heap_start;
+    fn init(&self) {
+        let (heap_start, heap_size) = heap_region();
+        unsafe {
+            self.lock().init(heap_start, heap_size);
+        }
     }
-}
 
-unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
-
-        let alloc_start = align_up(allocator.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return core::ptr::null_mut(),
-        };
-
-        if alloc_end > allocator.heap_end {
-            ptr::null_mut() // out of memory
-        } else {
-            allocator.next = alloc_end;
-            allocator.allocations += 1;
-            alloc_start as *mut u8
-        }
+        self.lock().alloc(layout)
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        let mut allocator = self.lock();
-
-        allocator.allocations -= 1;
-        if allocator.allocations == 0 {
-            allocator.next = allocator.heap_start;
-        }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().dealloc(ptr, layout)
     }
-}
\ No newline at end of file
+}
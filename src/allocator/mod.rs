@@ -1,52 +1,139 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::{cmp, ptr};
+use crate::arch::consts::get_page_align;
+use crate::arch::memory::{page_allocator, KMALLOC_HEAP, SLAB_HEAP};
+use crate::arch::trap::without_interrupts;
+use crate::dtb;
+
+pub use mm::align_up;
+
 pub mod lock;
 pub use lock::*;
 
-#[cfg(feature = "allocator_bump")]
 pub mod bump;
-#[cfg(feature = "allocator_bump")]
 pub use bump::*;
-#[cfg(feature = "allocator_bump")]
-#[cfg(not(feature = "allocator_fixed_size_block"))]
-#[global_allocator]
-pub static ALLOCATOR: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
 
-#[cfg(feature = "allocator_fixed_size_block")]
 pub mod fixed_sized_block;
-#[cfg(feature = "allocator_fixed_size_block")]
 pub use fixed_sized_block::*;
-#[cfg(feature = "allocator_fixed_size_block")]
+
+/// A kernel heap implementation the global allocator can dispatch to.
+///
+/// The methods follow the contract of [`GlobalAlloc`],
+/// failed allocations return null instead of panicking.
+/// The global allocator calls them with interrupts disabled.
+pub trait KernelHeap: Sync {
+    /// Name of the heap, as accepted by the `heap=` boot argument.
+    fn name(&self) -> &'static str;
+
+    /// Set up the memory of the heap. Called once if the heap is selected.
+    fn init(&self) {}
+
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8;
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout);
+
+    /// Resizes an allocation. By default the memory is moved to a new allocation.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        realloc_by_moving(self, ptr, layout, new_size)
+    }
+}
+
+/// Moves an allocation to a new one of `new_size` bytes from the same heap.
+pub unsafe fn realloc_by_moving<H: KernelHeap + ?Sized>(heap: &H, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let new_ptr = heap.alloc(new_layout);
+    if !new_ptr.is_null() {
+        ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+        heap.dealloc(ptr, layout);
+    }
+
+    new_ptr
+}
+
+/// Pages of the region managed by the bump and fixed-size-block heaps.
+const HEAP_PAGES: usize = 2048;
+
+static BUMP_HEAP: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
+static FIXED_SIZE_BLOCK_HEAP: Locked<FixedSizedBlockAllocator> = Locked::new(FixedSizedBlockAllocator::new());
+
+/// Every heap the kernel can run on.
+static HEAPS: [&dyn KernelHeap; 4] = [&SLAB_HEAP, &KMALLOC_HEAP, &FIXED_SIZE_BLOCK_HEAP, &BUMP_HEAP];
+
+/// Index into [`HEAPS`] of the heap used unless the boot arguments select another one.
+const DEFAULT_HEAP: usize = if cfg!(feature = "allocator_fixed_size_block") {
+    2
+} else if cfg!(feature = "allocator_bump") {
+    3
+} else {
+    0
+};
+
+/// Allocates the region for a heap that manages a fixed range of memory.
+/// Returns the start and size of the region.
+fn heap_region() -> (usize, usize) {
+    let start = page_allocator::zalloc(HEAP_PAGES).expect("out of memory for the kernel heap");
+    (start as usize, HEAP_PAGES * get_page_align())
+}
+
+/// The global allocator. It forwards every request to the heap selected at boot.
+struct GlobalHeap {
+    /// Index into [`HEAPS`].
+    selected: AtomicUsize,
+    /// Set by the first allocation. Afterwards the heap can not be changed anymore.
+    used: AtomicBool,
+}
+
+impl GlobalHeap {
+    fn heap(&self) -> &'static dyn KernelHeap {
+        HEAPS[self.selected.load(Ordering::Acquire)]
+    }
+}
+
+// Interrupts are disabled while a heap runs, because a thread may be
+// preempted and trap handlers allocate as well. Either would deadlock
+// when it happens on the hart that holds the lock of the heap.
+unsafe impl GlobalAlloc for GlobalHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.used.store(true, Ordering::Relaxed);
+        without_interrupts(|| self.heap().alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.heap().dealloc(ptr, layout))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        without_interrupts(|| self.heap().realloc(ptr, layout, new_size))
+    }
+}
+
 #[global_allocator]
-pub static KERNEL_HEAP_ALLOCATOR: Locked<FixedSizedBlockAllocator> = Locked::new(FixedSizedBlockAllocator::new());
-
-/// The kernel heap.
-/// This is a fixed size heap of 128 KiB.
-pub static KERNEL_HEAP: [u8; 0x0002_0000] = [0; 0x0002_0000];
-
-/// Initialize the kernel heap memory.
-pub unsafe fn init() {
-    #[cfg(feature = "allocator_bump")]
-    #[cfg(feature = "allocator_fixed_size_block")]
-    {
-        let heap_start = KERNEL_HEAP.as_ptr() as usize;
-        let heap_size = KERNEL_HEAP.len();
-        KERNEL_HEAP_ALLOCATOR.lock().init(heap_start, heap_size);
+static GLOBAL_HEAP: GlobalHeap = GlobalHeap {
+    selected: AtomicUsize::new(DEFAULT_HEAP),
+    used: AtomicBool::new(false),
+};
+
+/// Select the kernel heap from the `heap=` boot argument, defaulting to the one
+/// chosen by the allocator features, and set up its memory.
+/// Must be called after the kernel memory was initialized and before anything is allocated.
+pub fn init() {
+    if let Some(name) = dtb::boot_argument("heap") {
+        match HEAPS.iter().position(|heap| heap.name() == name) {
+            Some(_) if GLOBAL_HEAP.used.load(Ordering::Relaxed) => {
+                println!("| Heap {:?} ignored, memory was already allocated", name)
+            }
+            Some(index) => GLOBAL_HEAP.selected.store(index, Ordering::Release),
+            None => println!("| Unknown heap {:?}", name),
+        }
     }
+
+    let heap = GLOBAL_HEAP.heap();
+    heap.init();
+    println!("| Heap: {}", heap.name());
 }
 
-/// Align the address `addr` upwards to alignment `align`.
-/// # Safety
-/// `align` must be a power of two.
-/// # Logic
-/// The following is a more readable version of the align_up function.
-/// ```
-/// let remainder = addr % align;
-/// if remainder == 0 {
-///     addr // addr already aligned
-/// } else {
-///     addr - remainder + align
-/// }
-/// ```
-pub fn align_up(addr: usize, align: usize) -> usize {
-    assert!(align.is_power_of_two());
-    (addr + align - 1) & !(align - 1)
-}
\ No newline at end of file
+/// Returns the name of the heap behind the global allocator.
+pub fn heap_name() -> &'static str {
+    GLOBAL_HEAP.heap().name()
+}
@@ -30,6 +30,28 @@ pub unsafe fn get_hart_id() -> usize {
     hart_id
 }
 
+/// Get the hart id of the current hart.
+/// The boot code stores the hart id in the `tp` register,
+/// so unlike [`get_hart_id`] this can be called anywhere.
+#[inline(always)]
+pub fn hart_id() -> usize {
+    let hart_id: usize;
+    unsafe {
+        asm!("mv {}, tp", out(reg) hart_id, options(nomem, nostack));
+    }
+    hart_id
+}
+
+/// Read the current stack pointer.
+#[inline(always)]
+pub fn read_sp() -> usize {
+    let sp: usize;
+    unsafe {
+        asm!("mv {}, sp", out(reg) sp, options(nomem, nostack));
+    }
+    sp
+}
+
 #[inline(always)]
 pub fn get_mhartid() -> usize {
     let mhartid: usize;
@@ -57,6 +79,14 @@ pub fn write_satp(satp: usize) {
     }
 }
 
+/// Flush all address translations of the current hart.
+#[inline(always)]
+pub fn sfence_vma() {
+    unsafe {
+        asm!("sfence.vma zero, zero");
+    }
+}
+
 /// Check if virtual memory is enabled.
 #[inline(always)]
 pub fn is_virtual_memory_enabled() -> bool {
@@ -71,4 +101,14 @@ pub fn get_time() -> u64 {
         asm!("rdtime {}", out(reg) time);
     }
     time
-}
\ No newline at end of file
+}
+/// Write the `stimecmp` register of the Sstc extension.
+/// The supervisor timer interrupt is pending while `time >= stimecmp`.
+/// # Safety
+/// This traps with an illegal instruction if the Sstc extension
+/// is not implemented or not enabled by the SBI implementation.
+#[inline(always)]
+pub unsafe fn write_stimecmp(value: u64) {
+    // stimecmp is CSR 0x14D, the numeric form works without the extension enabled in the assembler.
+    asm!("csrw 0x14D, {}", in(reg) value, options(nomem, nostack));
+}
@@ -0,0 +1,98 @@
+use core::arch::asm;
+use crate::arch::consts::{get_memory_end, get_memory_start};
+
+/// Maximum number of frames walked before giving up.
+/// This protects against corrupted or cyclic frame chains.
+pub const MAX_FRAMES: usize = 64;
+
+/// A single frame of a call stack.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// The address execution continues at in this frame.
+    pub pc: usize,
+    /// The frame pointer of this frame.
+    pub fp: usize,
+}
+
+/// Read the frame pointer (`s0`) of the calling function.
+/// Requires the kernel to be built with `-C force-frame-pointers=yes`.
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp, options(nomem, nostack));
+    }
+    fp
+}
+
+/// Walks the `fp`/`ra` chain of the stack.
+///
+/// With frame pointers enabled every function stores its return
+/// address at `fp - 8` and the frame pointer of its caller at `fp - 16`.
+/// The chain ends at the zeroed frame pointer set up by the boot code.
+pub struct FrameIter {
+    fp: usize,
+    depth: usize,
+}
+
+impl FrameIter {
+    /// Start walking at the given frame pointer.
+    pub fn new(fp: usize) -> Self {
+        Self { fp, depth: 0 }
+    }
+
+    /// Check if the given frame pointer can be dereferenced safely.
+    fn is_valid_fp(fp: usize) -> bool {
+        fp % 8 == 0
+            && fp >= get_memory_start() + 16
+            && fp <= get_memory_end()
+    }
+}
+
+impl Iterator for FrameIter {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.depth >= MAX_FRAMES {
+            return None;
+        }
+
+        let (ra, prev_fp) = frame_record(self.fp)?;
+
+        if ra == 0 {
+            return None;
+        }
+
+        let frame = Frame { pc: ra, fp: self.fp };
+
+        // Stacks grow downwards, so the caller's frame must be above ours.
+        // Everything else is a corrupted chain and ends the walk.
+        self.fp = if prev_fp > self.fp { prev_fp } else { 0 };
+        self.depth += 1;
+
+        Some(frame)
+    }
+}
+
+/// Read the frame record at the given frame pointer.
+/// Returns `(return address, caller frame pointer)`
+/// or None if the frame pointer is invalid.
+pub fn frame_record(fp: usize) -> Option<(usize, usize)> {
+    if !FrameIter::is_valid_fp(fp) {
+        return None;
+    }
+
+    unsafe {
+        let record = fp as *const usize;
+        Some((*record.sub(1), *record.sub(2)))
+    }
+}
+
+/// Returns the frame pointer of the code that was interrupted by a trap.
+///
+/// `handler_fp` must be the frame pointer of the trap handler itself.
+/// The handler saves the interrupted `s0` in its frame record,
+/// just like any other function saves the frame pointer of its caller.
+pub fn interrupted_frame_pointer(handler_fp: usize) -> usize {
+    frame_record(handler_fp).map(|(_, fp)| fp).unwrap_or(0)
+}
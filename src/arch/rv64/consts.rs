@@ -89,6 +89,25 @@ pub fn get_stack_size() -> usize {
     unsafe { STACK_END - STACK_START }
 }
 
+/// Returns the size of the stack of a single hart in bytes.
+/// This includes the guard page at the bottom of the stack
+/// and must be kept in sync with the boot code and the linker script.
+#[inline(always)]
+pub fn get_hart_stack_size() -> usize {
+    0x10000
+}
+
+/// Returns the size of the unmapped guard region
+/// at the bottom of every hart stack in bytes.
+#[inline(always)]
+pub fn get_stack_guard_size() -> usize {
+    4096
+}
+
+/// The maximum number of harts the kernel reserves stacks for.
+/// This must be kept in sync with the linker script.
+pub const MAX_HARTS: usize = 5;
+
 /// Returns the address of the kernel heap start.
 #[inline(always)]
 pub fn get_heap_start() -> usize {
@@ -127,6 +146,8 @@ pub fn print_consts() {
     println!("| Stack Start: {:#x}", get_stack_start());
     println!("| Stack End: {:#x}", get_stack_end());
     println!("| Stack Size: {:#x}", get_stack_size());
+    println!("| Hart Stack Size: {:#x}", get_hart_stack_size());
+    println!("| Stack Guard Size: {:#x}", get_stack_guard_size());
     println!("| Heap Start: {:#x}", get_heap_start());
     println!("| Heap Size: {:#x}", get_heap_size());
     println!("| Pages Size: {:#x}", get_pages_size());
@@ -0,0 +1,61 @@
+use core::arch::asm;
+
+/// Supervisor interrupt enable.
+const SSTATUS_SIE: usize = 1 << 1;
+/// Supervisor previous interrupt enable.
+const SSTATUS_SPIE: usize = 1 << 5;
+/// Supervisor previous privilege.
+const SSTATUS_SPP: usize = 1 << 8;
+
+extern "C" {
+    /// Defined in `asm/switch.S`.
+    fn switch_to(current: *mut Context, next: *const Context);
+}
+
+/// Registers of a kernel thread that is not running.
+/// This must be kept in sync with `asm/switch.S`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Context {
+    ra: usize,
+    sp: usize,
+    /// s0 - s11
+    s: [usize; 12],
+    sstatus: usize,
+}
+
+impl Context {
+    /// An empty context, filled in when the current code is switched out.
+    pub const fn empty() -> Self {
+        Self { ra: 0, sp: 0, s: [0; 12], sstatus: 0 }
+    }
+
+    /// A context that starts executing `entry` on the given stack.
+    /// `entry` runs with interrupts disabled.
+    pub fn new(entry: extern "C" fn() -> !, stack_top: usize) -> Self {
+        let sstatus: usize;
+        unsafe {
+            asm!("csrr {}, sstatus", out(reg) sstatus, options(nomem, nostack));
+        }
+
+        Self {
+            ra: entry as usize,
+            sp: stack_top,
+            // s0 is the frame pointer, zero ends backtraces.
+            s: [0; 12],
+            sstatus: (sstatus & !SSTATUS_SIE) | SSTATUS_SPIE | SSTATUS_SPP,
+        }
+    }
+}
+
+/// Save the registers of the running code to `current` and continue with `next`.
+/// Returns once another switch loads `current` again.
+///
+/// # Safety
+/// Interrupts must be disabled and `next` must have been saved by a previous
+/// switch or created with [`Context::new`]. Neither context may be used
+/// by another hart at the same time.
+#[inline(always)]
+pub unsafe fn switch(current: *mut Context, next: *const Context) {
+    switch_to(current, next);
+}
@@ -3,19 +3,26 @@ use alloc::string::String;
 use core::arch::global_asm;
 use core::ops::Add;
 use opensbi::hart_start;
-use opensbi::time::set_timer;
 use crate::arch::consts::{print_consts, _kentry, get_heap_size, get_page_align, get_heap_start, get_text_start, get_text_end, get_rodata_start, get_rodata_end, get_data_start, get_data_end, get_bss_start, get_bss_end, get_stack_start, get_stack_end};
 use crate::arch::logger::OpenSbiLogger;
 use crate::arch::paging_sv39;
 use crate::arch::paging_sv39::{EntryBits};
-use crate::arch::rv64::asm::{get_hart_id, get_time, is_virtual_memory_enabled, read_satp};
+use crate::arch::rv64::asm::{hart_id, is_virtual_memory_enabled, read_satp};
+use crate::arch::rv64::{plic, stack};
 use crate::arch::rv64::trap::enable_s_mode_traps;
-use crate::arch::rv64::memory::{kernel_allocator, page_allocator};
-use crate::arch::trap::enable_timer_interrupts;
+use crate::arch::rv64::memory::{kernel_allocator, page_allocator, page_benchmark};
+use crate::allocator;
+use crate::dtb;
+use crate::drivers::goldfish_rtc;
 use crate::logger::LOGGER;
+use crate::smp;
+use crate::thread;
+use crate::time;
 
 global_asm!(include_str!("asm/memory.S"));
 global_asm!(include_str!("asm/boot.S"));
+global_asm!(include_str!("asm/trap.S"));
+global_asm!(include_str!("asm/switch.S"));
 
 /// Test of zero values in BSS.
 static BSS_TEST_ZERO: usize = 0;
@@ -24,18 +31,19 @@ static DATA_TEST_NONZERO: usize = 0xFFFF_FFFF_FFFF_FFFF;
 
 #[no_mangle]
 pub unsafe extern "C" fn kentry(hart_id: usize, dtb: usize) -> ! {
+    stack::paint(hart_id);
+
     assert_eq!(BSS_TEST_ZERO, 0);
     assert_eq!(DATA_TEST_NONZERO, 0xFFFF_FFFF_FFFF_FFFF);
 
+    if let Err(error) = dtb::init(dtb) {
+        println!("Failed to parse device tree at {:#x}: {:?}", dtb, error);
+    }
+
     println!("Initializing trap handling...");
     enable_s_mode_traps();
     println!("Trap handling initialized");
 
-    println!("Initializing timer...");
-    set_timer(get_time() + 10_000_000);
-    enable_timer_interrupts();
-    println!("Timer initialized");
-
     println!("+ Booting RiskyOS ");
     println!("| Made by: DokkaeCat <linfia21@htl-kaindorf.at>");
     println!("| Started on Hart: {}", hart_id);
@@ -49,6 +57,7 @@ pub unsafe extern "C" fn kentry(hart_id: usize, dtb: usize) -> ! {
 
     println!("Initializing kernel memory...");
     kernel_allocator::init();
+    allocator::init();
     println!("Kernel memory initialized");
 
     println!("+ Initializing virtual memory...");
@@ -61,8 +70,32 @@ pub unsafe extern "C" fn kentry(hart_id: usize, dtb: usize) -> ! {
     LOGGER.set_logger(Box::new(OpenSbiLogger));
     println!("Logger initialized");
 
+    println!("+ Initializing interrupt controller...");
+    plic::init();
+    smp::init_hart();
+
+    println!("+ Initializing timers...");
+    time::init();
+    time::timer::init_hart();
+
+    println!("+ Initializing real time clock...");
+    goldfish_rtc::init();
+    time::wall_clock::init();
+
+    println!("+ Initializing threads...");
+    thread::policy::init();
+    thread::scheduler::init_hart();
+
+    if dtb::boot_argument("pagebench").is_some() {
+        page_benchmark::run();
+    }
+
     println!("+ Starting other harts...");
     for hid in 0..4 {
+        if hid != hart_id {
+            stack::paint(hid);
+        }
+
         // 0xc0ffee is the argument passed to the kernel entry point inside the a1 register.
         // This is a weird workaround because the hart should already
         // be inside kentry_ap but instead it starts executing way before that.
@@ -71,12 +104,21 @@ pub unsafe extern "C" fn kentry(hart_id: usize, dtb: usize) -> ! {
         println!("| Starting Hart {}: {:?}", hid, result);
     }
 
+    stack::print_stack_usage();
+
     crate::kmain();
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn kentry_ap() -> ! {
-    let hart_id = get_hart_id();
+    let hart_id = hart_id();
+
+    enable_s_mode_traps();
+    paging_sv39::enable();
+    plic::init_hart();
+    smp::init_hart();
+    time::timer::init_hart();
+    thread::scheduler::init_hart();
 
     println!("Hart {} started (AP)", hart_id);
 
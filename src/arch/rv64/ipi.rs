@@ -0,0 +1,21 @@
+use opensbi::SbiRet;
+use crate::arch::trap::{clear_software_interrupt, enable_software_interrupts};
+
+/// Send a supervisor software interrupt to all harts in the mask.
+/// Bit `n` of the mask selects hart `n`.
+#[inline(always)]
+pub fn send_ipi(hart_mask: usize) -> SbiRet<usize> {
+    opensbi::send_ipi(hart_mask, 0)
+}
+
+/// Allow the current hart to receive software interrupts.
+#[inline(always)]
+pub fn enable_ipi() {
+    enable_software_interrupts();
+}
+
+/// Acknowledge a software interrupt on the current hart.
+#[inline(always)]
+pub fn clear_ipi() {
+    clear_software_interrupt();
+}
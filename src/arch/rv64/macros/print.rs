@@ -3,6 +3,23 @@ use crate::arch::logger::OpenSbiLogger;
 
 pub static PRINT_LOCK: Mutex<OpenSbiLogger> = Mutex::new(OpenSbiLogger);
 
+/// Make sure the console can be used, even if another hart
+/// (or the current one) never releases the print lock.
+/// This is only meant to be used when the system is going down.
+pub fn take_over_console() {
+    // Give the current owner a chance to finish its line.
+    for _ in 0..1_000_000 {
+        if !PRINT_LOCK.is_locked() {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+
+    unsafe {
+        PRINT_LOCK.force_unlock();
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($($args:tt)+) => {{
@@ -0,0 +1,258 @@
+use core::ptr;
+use crate::arch::consts::get_page_align;
+use crate::arch::rv64::memory::page::{Page, PageBits, NO_PAGE};
+
+/// Largest block order. A block of order `n` consists of `2^n` pages.
+pub const MAX_ORDER: usize = 16;
+
+/// Binary buddy allocator over a contiguous range of pages.
+///
+/// Free pages are kept in blocks of a power of two pages, aligned to their size
+/// relative to the first page. Every order has its own free list, linked through
+/// the page descriptors, so neither allocating nor freeing has to scan the pages.
+/// A freed block is merged with its buddy, the other half of the block of the
+/// next order, as long as the buddy is free as well.
+///
+/// Runs that are not a power of two take the smallest block that fits them,
+/// the unused tail of that block is freed right away.
+pub struct BuddyAllocator {
+    /// One descriptor for every page.
+    descriptors: *mut Page,
+    /// Address of the first page.
+    base: usize,
+    pages: usize,
+    /// First free block of every order.
+    free_lists: [u32; MAX_ORDER + 1],
+    free_pages: usize,
+    allocations: u64,
+    frees: u64,
+}
+
+// The descriptors are only accessed through the allocator.
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    /// An allocator without any pages.
+    pub const fn empty() -> Self {
+        Self {
+            descriptors: ptr::null_mut(),
+            base: 0,
+            pages: 0,
+            free_lists: [NO_PAGE; MAX_ORDER + 1],
+            free_pages: 0,
+            allocations: 0,
+            frees: 0,
+        }
+    }
+
+    /// Creates an allocator for `pages` pages starting at `base`, all of them free.
+    /// # Safety
+    /// `descriptors` must point to writable memory for `pages` descriptors
+    /// and `base` must be page aligned. Both belong to the allocator from now on.
+    pub unsafe fn new(descriptors: *mut Page, base: usize, pages: usize) -> Self {
+        assert!(pages < NO_PAGE as usize, "too many pages for the buddy allocator");
+
+        for i in 0..pages {
+            descriptors.add(i).write(Page::empty());
+        }
+
+        let mut allocator = Self { descriptors, base, pages, ..Self::empty() };
+        allocator.free_range(0, pages);
+        allocator
+    }
+
+    /// Address of the first page.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn total_pages(&self) -> usize {
+        self.pages
+    }
+
+    pub fn free_pages(&self) -> usize {
+        self.free_pages
+    }
+
+    /// Number of successful allocations since the allocator was created.
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
+    /// Number of frees since the allocator was created.
+    pub fn frees(&self) -> u64 {
+        self.frees
+    }
+
+    /// Length in pages of the longest run of free pages.
+    /// Neighbouring free blocks count as one run, even if they are no buddies.
+    pub fn largest_free_run(&self) -> usize {
+        let mut largest = 0;
+        let mut current = 0;
+        let mut index = 0;
+
+        while index < self.pages {
+            let page = self.page_ref(index);
+            if page.is_free() {
+                current += 1 << page.order;
+                largest = largest.max(current);
+                index += 1 << page.order;
+            } else {
+                current = 0;
+                index += if page.is_taken() { page.run as usize } else { 1 };
+            }
+        }
+
+        largest
+    }
+
+    /// Allocates a contiguous run of pages.
+    /// Returns a pointer to the first page or None if no block is large enough.
+    pub fn alloc(&mut self, pages: usize) -> Option<*mut u8> {
+        assert!(pages > 0);
+
+        let order = pages.next_power_of_two().trailing_zeros() as usize;
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let index = self.alloc_block(order)?;
+        // Give back what the run does not need.
+        self.free_range(index + pages, (1 << order) - pages);
+
+        let page = self.page(index);
+        page.set_flag(PageBits::Taken);
+        page.run = pages as u32;
+        self.allocations += 1;
+
+        Some((self.base + index * get_page_align()) as *mut u8)
+    }
+
+    /// Frees the run of pages starting at `ptr`.
+    /// # Safety
+    /// The pointer must point to the first page of a run returned by [`BuddyAllocator::alloc`].
+    /// Otherwise the function will panic.
+    pub fn dealloc(&mut self, ptr: *mut u8) {
+        let addr = ptr as usize;
+        assert!(addr >= self.base && addr < self.base + self.pages * get_page_align(), "pointer is out of bounds");
+        assert_eq!(addr % get_page_align(), 0, "pointer is not page aligned");
+
+        let index = (addr - self.base) / get_page_align();
+        let page = self.page(index);
+        assert!(page.is_taken(), "possible double-free detected");
+
+        let run = page.run as usize;
+        page.clear();
+        self.free_range(index, run);
+        self.frees += 1;
+    }
+
+    /// Calls `f` with the address and length in pages of every allocated run.
+    pub fn for_each_run(&self, mut f: impl FnMut(usize, usize)) {
+        let mut index = 0;
+        while index < self.pages {
+            let page = self.page_ref(index);
+            if page.is_taken() {
+                f(self.base + index * get_page_align(), page.run as usize);
+                index += page.run as usize;
+            } else if page.is_free() {
+                index += 1 << page.order;
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    fn page(&mut self, index: usize) -> &mut Page {
+        debug_assert!(index < self.pages);
+        unsafe { &mut *self.descriptors.add(index) }
+    }
+
+    fn page_ref(&self, index: usize) -> &Page {
+        debug_assert!(index < self.pages);
+        unsafe { &*self.descriptors.add(index) }
+    }
+
+    /// Takes a free block of the given order, splitting a larger one if needed.
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        let found = (order..=MAX_ORDER).find(|&order| self.free_lists[order] != NO_PAGE)?;
+        let index = self.free_lists[found] as usize;
+        self.remove(index, found);
+
+        // The upper halves of the split become free blocks of the lower orders.
+        for order in (order..found).rev() {
+            self.push(index + (1 << order), order);
+        }
+
+        Some(index)
+    }
+
+    /// Frees the pages `start..start + count` as the largest aligned blocks they contain.
+    fn free_range(&mut self, mut start: usize, mut count: usize) {
+        while count > 0 {
+            let order = (start.trailing_zeros() as usize)
+                .min(count.ilog2() as usize)
+                .min(MAX_ORDER);
+
+            self.free_block(start, order);
+            start += 1 << order;
+            count -= 1 << order;
+        }
+    }
+
+    /// Frees a single block and merges it with its buddies.
+    fn free_block(&mut self, mut index: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+            if buddy + (1 << order) > self.pages {
+                break;
+            }
+
+            let page = self.page_ref(buddy);
+            if !page.is_free() || page.order as usize != order {
+                break;
+            }
+
+            self.remove(buddy, order);
+            index = index.min(buddy);
+            order += 1;
+        }
+
+        self.push(index, order);
+    }
+
+    /// Adds a block to the front of the free list of its order.
+    fn push(&mut self, index: usize, order: usize) {
+        let head = self.free_lists[order];
+        if head != NO_PAGE {
+            self.page(head as usize).prev = index as u32;
+        }
+
+        let page = self.page(index);
+        page.clear();
+        page.set_flag(PageBits::Free);
+        page.order = order as u8;
+        page.next = head;
+
+        self.free_lists[order] = index as u32;
+        self.free_pages += 1 << order;
+    }
+
+    /// Unlinks a block from the free list of its order.
+    fn remove(&mut self, index: usize, order: usize) {
+        let Page { next, prev, .. } = *self.page_ref(index);
+
+        if prev == NO_PAGE {
+            self.free_lists[order] = next;
+        } else {
+            self.page(prev as usize).next = next;
+        }
+
+        if next != NO_PAGE {
+            self.page(next as usize).prev = prev;
+        }
+
+        self.page(index).clear();
+        self.free_pages -= 1 << order;
+    }
+}
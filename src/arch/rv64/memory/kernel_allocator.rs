@@ -1,20 +1,107 @@
-use core::intrinsics::size_of;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use mm::alloc_list::{self, AllocList};
 use crate::allocator::align_up;
-use crate::arch::consts::get_page_size;
+use crate::arch::consts::get_page_align;
 use crate::arch::paging_sv39::Table;
-use crate::arch::rv64::memory::alloc_list::AllocList;
+use crate::arch::rv64::memory::fragmentation;
 use crate::arch::rv64::memory::page_allocator;
 use crate::arch::rv64::memory::page_allocator::zalloc;
 
-static mut KMEM_HEAD: Option<*mut AllocList> = None;
+/// Pages of the arena set up by [`init`]. It is never given back.
+const INITIAL_ARENA_PAGES: usize = 512;
+/// Smallest number of pages the heap grows by.
+const ARENA_PAGES: usize = 64;
+
+/// The first arena of the kernel heap.
+static mut KMEM_HEAD: Option<*mut Arena> = None;
 /// The amount of memory (in pages) allocated by the kernel.
 static mut KMEM_ALLOCATED: usize = 0;
 static mut KMEM_PAGE_TABLE: Option<*mut Table> = None;
 static mut IS_INITIALIZED: AtomicBool = AtomicBool::new(false);
+static KMEM_ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static KMEM_FREES: AtomicU64 = AtomicU64::new(0);
+
+/// Usage of the kmalloc heap. Sizes include the allocation headers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KmemStats {
+    /// Size of the heap in bytes.
+    pub total_bytes: usize,
+    /// Number of regions the heap is made of.
+    pub arenas: usize,
+    pub used_bytes: usize,
+    pub free_bytes: usize,
+    /// Size of the largest free block in bytes.
+    pub largest_free_block: usize,
+    /// Successful allocations since boot.
+    pub allocations: u64,
+    /// Frees since boot.
+    pub frees: u64,
+}
+
+impl KmemStats {
+    /// Share of the free bytes outside of the largest free block, in parts per million.
+    /// 0 means all free memory is in one block.
+    pub fn fragmentation(&self) -> u64 {
+        fragmentation(self.largest_free_block, self.free_bytes)
+    }
+}
 
-pub fn get_head() -> Option<*mut AllocList> {
-    unsafe { KMEM_HEAD }
+/// Header of a region the kernel heap got from the page allocator.
+/// The allocation list of the arena follows right after it.
+///
+/// Arenas are kept in a linked list, so the heap can grow
+/// into any free pages instead of only the ones after it.
+#[repr(C)]
+struct Arena {
+    next: Option<*mut Arena>,
+    pages: usize,
+}
+
+impl Arena {
+    /// Set up an arena with a single free node in `pages` pages starting at `ptr`.
+    unsafe fn init(ptr: *mut u8, pages: usize) -> *mut Arena {
+        let arena = ptr as *mut Arena;
+        arena.write(Arena { next: None, pages });
+
+        AllocList::init((*arena).first(), (*arena).size());
+        arena
+    }
+
+    /// The first node of the allocation list.
+    fn first(&self) -> *mut AllocList {
+        unsafe { (self as *const Arena).add(1) as *mut AllocList }
+    }
+
+    /// The end of the allocation list.
+    fn end(&self) -> *mut AllocList {
+        (self as *const Arena as usize + self.pages * get_page_align()) as *mut AllocList
+    }
+
+    /// Size of the allocation list in bytes.
+    fn size(&self) -> usize {
+        self.pages * get_page_align() - size_of::<Arena>()
+    }
+
+    fn contains(&self, ptr: *mut u8) -> bool {
+        (self.first() as *mut u8) < ptr && ptr < self.end() as *mut u8
+    }
+
+    /// Check if nothing is allocated in the arena.
+    /// Only correct after the arena was coalesced.
+    fn is_empty(&self) -> bool {
+        unsafe { (*self.first()).is_free() && (*self.first()).size() == self.size() }
+    }
+}
+
+/// Iterate over all arenas of the kernel heap.
+fn arenas() -> impl Iterator<Item = *mut Arena> {
+    unsafe { core::iter::successors(KMEM_HEAD, |&arena| (*arena).next) }
+}
+
+/// Returns the start of the first arena of the kernel heap.
+pub fn get_head() -> Option<*mut u8> {
+    unsafe { KMEM_HEAD.map(|arena| arena as *mut u8) }
 }
 
 /// Wrapper around the kmem allocated variable to allow for safe access.
@@ -49,11 +136,9 @@ pub fn init() {
     assert!(page_allocator::is_initialized(), "page allocator not initialized");
 
     unsafe {
-        KMEM_ALLOCATED = 512;
-        let alloc_list = zalloc(KMEM_ALLOCATED).expect("out of memory") as *mut AllocList;
-        KMEM_HEAD = Some(alloc_list);
-        (*KMEM_HEAD.unwrap()).set_free();
-        (*KMEM_HEAD.unwrap()).set_size(KMEM_ALLOCATED * get_page_size());
+        KMEM_ALLOCATED = INITIAL_ARENA_PAGES;
+        let memory = zalloc(INITIAL_ARENA_PAGES).expect("out of memory");
+        KMEM_HEAD = Some(Arena::init(memory, INITIAL_ARENA_PAGES));
 
         let page_table = zalloc(1).expect("out of memory") as *mut Table;
         KMEM_PAGE_TABLE = Some(page_table);
@@ -65,48 +150,105 @@ pub fn init() {
 /// The size of the memory to allocate is specified in bits and
 /// will be aligned to the next 8 byte boundary.
 ///
+/// If no arena has a large enough free block, the heap grows
+/// by a new arena from the page allocator.
+///
 /// This function will return a pointer to the allocated memory
 /// if successful, or None if the allocation failed.
 ///
 /// The allocation will fail if:
 /// - The size is 0
 /// - The kernel memory system has not been initialized
+/// - The page allocator is out of memory
 pub fn kmalloc(size: usize) -> Option<*mut u8> {
+    kmalloc_aligned(size, 8)
+}
+
+/// Allocates sub-page level memory in kernel space,
+/// aligned to `align` bytes, which must be a power of two.
+/// Alignments below 8 bytes are raised to 8 bytes.
+///
+/// See [`kmalloc`] for when the allocation fails.
+pub fn kmalloc_aligned(size: usize, align: usize) -> Option<*mut u8> {
     assert!(is_initialized(), "kernel memory system not initialized");
     assert!(size > 0, "allocation size must be greater than 0");
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+    let aligned_size = alloc_list::aligned_size(size);
+    let align = align.max(8);
 
     unsafe {
-        let aligned_size = align_up(size, 8) + size_of::<AllocList>();
-        let mut head = KMEM_HEAD?;
-        let tail = (KMEM_HEAD? as *mut u8).add(KMEM_ALLOCATED * get_page_size()) as *mut AllocList;
-
-        while head < tail {
-            if (*head).is_free() && aligned_size <= (*head).size() {
-                let chunk_size = (*head).size();
-                let remaining_size = chunk_size - aligned_size;
-                (*head).set_taken();
-
-                if remaining_size > size_of::<AllocList>() {
-                    let new_head = (head as *mut u8).add(aligned_size) as *mut AllocList;
-                    (*new_head).set_free();
-                    (*new_head).set_size(remaining_size);
-                    (*head).set_size(aligned_size);
-                } else {
-                    (*head).set_size(chunk_size);
-                }
+        let ptr = match arenas().find_map(|arena| alloc_in(arena, aligned_size, align)) {
+            Some(ptr) => ptr,
+            // Leave room for the free node in front of the aligned allocation.
+            None => alloc_in(grow(aligned_size + alloc_list::align_padding(align))?, aligned_size, align)?,
+        };
 
-                return Some(head.add(1) as *mut u8);
-            } else {
-                // If we get here, what we saw wasn't free or big enough.
-                // Move to the next node.
-                head = (head as *mut u8).add((*head).size()) as *mut AllocList;
-            }
+        KMEM_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        Some(ptr)
+    }
+}
+
+/// Allocates `aligned_size` bytes, including the header, from a single arena.
+/// The memory after the header is aligned to `align` bytes.
+unsafe fn alloc_in(arena: *mut Arena, aligned_size: usize, align: usize) -> Option<*mut u8> {
+    alloc_list::alloc((*arena).first(), (*arena).end(), aligned_size, align)
+}
+
+/// Resizes an allocation made by [`kmalloc`] without moving it.
+/// Growing only works if the node after the allocation is free and large enough.
+/// Returns false if the allocation has to be moved instead.
+///
+/// # Safety
+/// This function will panic if the pointer is null
+/// or does not point into the kernel heap.
+pub fn krealloc_in_place(ptr: *mut u8, size: usize) -> bool {
+    assert!(is_initialized(), "kernel memory system not initialized");
+    assert!(!ptr.is_null(), "can not reallocate a null pointer");
+
+    let aligned_size = alloc_list::aligned_size(size.max(1));
+
+    unsafe {
+        let arena = arenas()
+            .find(|&arena| (*arena).contains(ptr))
+            .expect("pointer is not part of the kernel heap");
+        let head = alloc_list::header(ptr);
+        assert!((*head).is_taken(), "can not reallocate freed memory");
+
+        if !alloc_list::resize(head, (*arena).end(), aligned_size) {
+            return false;
         }
+        coalesce(arena);
     }
 
-    // If we get here, we couldn't find a free block.
-    // TODO: Implement a way to allocate more memory.
-    None
+    true
+}
+
+/// Adds a new arena with room for at least `aligned_size` bytes to the heap.
+/// Returns None if the page allocator is out of memory.
+unsafe fn grow(aligned_size: usize) -> Option<*mut Arena> {
+    let pages = (aligned_size + size_of::<Arena>())
+        .div_ceil(get_page_align())
+        .max(ARENA_PAGES);
+    let arena = Arena::init(page_allocator::alloc(pages)?, pages);
+
+    // Append it, so the older arenas are searched first.
+    let last = arenas().last().expect("kernel memory system not initialized");
+    (*last).next = Some(arena);
+    KMEM_ALLOCATED += pages;
+
+    Some(arena)
+}
+
+/// Gives an empty arena back to the page allocator.
+unsafe fn release(arena: *mut Arena) {
+    let previous = arenas()
+        .find(|&previous| (*previous).next == Some(arena))
+        .expect("arena is not part of the kernel heap");
+
+    (*previous).next = (*arena).next;
+    KMEM_ALLOCATED -= (*arena).pages;
+    page_allocator::dealloc(arena as *mut u8);
 }
 
 /// Allocates sub-page level memory in kernel space and zeroes it.
@@ -126,55 +268,74 @@ pub fn kzmalloc(size: usize) -> Option<*mut u8> {
 }
 
 /// Frees memory allocated by [`kmalloc`].
+/// Arenas that become empty are given back to the page allocator,
+/// except for the first one.
 ///
 /// # Safety
-/// This function will panic if the pointer is null.
+/// This function will panic if the pointer is null
+/// or does not point into the kernel heap.
 pub fn kfree(ptr: *mut u8) {
     assert!(is_initialized(), "kernel memory system not initialized");
     assert!(!ptr.is_null(), "can not deallocate a null pointer");
 
     unsafe {
-        let p = (ptr as *mut AllocList).offset(-1);
+        let arena = arenas()
+            .find(|&arena| (*arena).contains(ptr))
+            .expect("pointer is not part of the kernel heap");
+        let p = alloc_list::header(ptr);
 
         if (*p).is_taken() {
             (*p).set_free();
+            KMEM_FREES.fetch_add(1, Ordering::Relaxed);
         }
 
         // Coalesce the free blocks.
         // This tries to reduce fragmentation by merging adjacent free blocks.
-        coalesce();
+        coalesce(arena);
+
+        if Some(arena) != KMEM_HEAD && (*arena).is_empty() {
+            release(arena);
+        }
     }
 }
 
-/// Coalesces (Merges) adjacent free blocks in the kernel memory system.
+/// Coalesces (Merges) adjacent free blocks in an arena.
 /// This function is called after a block of memory is freed to reduce fragmentation.
+unsafe fn coalesce(arena: *mut Arena) {
+    alloc_list::coalesce((*arena).first(), (*arena).end());
+}
+
+/// Returns the current usage of the kmalloc heap.
+/// The caller has to hold the lock of the global allocator.
 ///
 /// # Safety
 /// This function will panic if the kernel memory system has not been initialized.
-fn coalesce() {
-    unsafe {
-        let mut head = KMEM_HEAD.expect("kernel memory system not initialized");
-        let tail = (KMEM_HEAD.unwrap() as *mut u8).add(KMEM_ALLOCATED * get_page_size()) as *mut AllocList;
-
-        while head < tail {
-            let next = (head as *mut u8).add((*head).size()) as *mut AllocList;
-
-            if (*head).size() == 0 {
-                // If the size of the current node is 0, we have
-                // a bad heap cause by a double free (I think).
-                break;
-            } else if next >= tail {
-                // If the next node is out of bounds, we're done.
-                break;
-            } else if (*head).is_free() && (*next).is_free() {
-                // If the current node and the next node are free,
-                // we can merge them into one node.
-                (*head).set_size((*head).size() + (*next).size());
-            }
+pub fn stats() -> KmemStats {
+    assert!(is_initialized(), "kernel memory system not initialized");
+
+    let mut stats = KmemStats {
+        allocations: KMEM_ALLOCATIONS.load(Ordering::Relaxed),
+        frees: KMEM_FREES.load(Ordering::Relaxed),
+        ..KmemStats::default()
+    };
 
-            head = (head as *mut u8).add((*head).size()) as *mut AllocList;
+    for arena in arenas() {
+        unsafe {
+            stats.total_bytes += (*arena).size();
+            stats.arenas += 1;
+
+            for head in alloc_list::nodes((*arena).first(), (*arena).end()) {
+                if (*head).is_free() {
+                    stats.free_bytes += (*head).size();
+                    stats.largest_free_block = stats.largest_free_block.max((*head).size());
+                } else {
+                    stats.used_bytes += (*head).size();
+                }
+            }
         }
     }
+
+    stats
 }
 
 /// For debugging purposes, print the kmem table.
@@ -182,19 +343,20 @@ fn coalesce() {
 /// # Safety
 /// This function will panic if the kernel memory system has not been initialized.
 pub fn print_kmem_table() {
-    unsafe {
-        let mut head = KMEM_HEAD.expect("kernel memory system not initialized");
-        let tail = (KMEM_HEAD.unwrap() as *mut u8).add(KMEM_ALLOCATED * get_page_size())
-            as *mut AllocList;
-        while head < tail {
-            println!(
-                "{:p}: Length = {:<10} Taken = {}",
-                head,
-                (*head).size(),
-                (*head).is_taken()
-            );
-            head = (head as *mut u8).add((*head).size())
-                as *mut AllocList;
+    assert!(is_initialized(), "kernel memory system not initialized");
+
+    for arena in arenas() {
+        unsafe {
+            println!("Arena {:p}: {} pages", arena, (*arena).pages);
+
+            for head in alloc_list::nodes((*arena).first(), (*arena).end()) {
+                println!(
+                    "{:p}: Length = {:<10} Taken = {}",
+                    head,
+                    (*head).size(),
+                    (*head).is_taken()
+                );
+            }
         }
     }
-}
\ No newline at end of file
+}
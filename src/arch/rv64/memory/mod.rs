@@ -1,30 +1,167 @@
-use core::alloc::{GlobalAlloc, Layout};
-use crate::allocator::Locked;
-use crate::arch::rv64::memory::kernel_allocator::{kfree, kzmalloc};
+use core::alloc::Layout;
+use core::ptr;
+use crate::allocator::{heap_name, realloc_by_moving, KernelHeap, Locked};
+use crate::arch::consts::get_page_align;
+use crate::arch::rv64::memory::kernel_allocator::{kfree, kmalloc_aligned, krealloc_in_place, KmemStats};
+use crate::arch::rv64::memory::page_allocator::PageStats;
+use crate::arch::trap::without_interrupts;
 
 pub mod page_allocator;
+pub mod page_benchmark;
+pub mod buddy;
+pub mod slab;
 pub mod kernel_allocator;
 pub mod page;
-pub mod alloc_list;
 
-struct KernelGlobalAlloc;
+/// Usage of the page allocator and the kmalloc heap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemStats {
+    pub pages: PageStats,
+    pub kmem: KmemStats,
+}
+
+/// Returns the current usage of both kernel allocators.
+pub fn stats() -> MemStats {
+    MemStats {
+        pages: page_allocator::stats(),
+        kmem: without_interrupts(|| {
+            let _guard = KMALLOC_HEAP.lock();
+            kernel_allocator::stats()
+        }),
+    }
+}
+
+/// Print the usage of both kernel allocators.
+pub fn print_stats() {
+    let stats = stats();
+    let pages = stats.pages;
+    let kmem = stats.kmem;
+
+    println!("+ Memory ({} heap):", heap_name());
+    println!(
+        "| Pages: {} used, {} free of {}, largest free run {}, {}.{}% fragmented, {} allocs, {} frees",
+        pages.used_pages,
+        pages.free_pages,
+        pages.total_pages,
+        pages.largest_free_run,
+        pages.fragmentation() / 10_000,
+        pages.fragmentation() / 1_000 % 10,
+        pages.allocations,
+        pages.frees
+    );
+    println!(
+        "| Kmalloc: {} bytes used, {} free of {} in {} arenas, largest free block {}, {}.{}% fragmented, {} allocs, {} frees",
+        kmem.used_bytes,
+        kmem.free_bytes,
+        kmem.total_bytes,
+        kmem.arenas,
+        kmem.largest_free_block,
+        kmem.fragmentation() / 10_000,
+        kmem.fragmentation() / 1_000 % 10,
+        kmem.allocations,
+        kmem.frees
+    );
+}
+
+/// Share of `free` that lies outside of the `largest` free region, in parts per million.
+fn fragmentation(largest: usize, free: usize) -> u64 {
+    if free == 0 {
+        return 0;
+    }
+
+    ((free - largest) as u64 * 1_000_000) / free as u64
+}
+
+/// Heap on the kmalloc list. Page aligned requests go to the page allocator directly.
+pub struct KmallocHeap;
+
+/// The lock protects the kmalloc list.
+pub static KMALLOC_HEAP: Locked<KmallocHeap> = Locked::new(KmallocHeap);
+
+impl KmallocHeap {
+    fn uses_pages(layout: &Layout) -> bool {
+        layout.align() == get_page_align()
+    }
+}
+
+impl KernelHeap for Locked<KmallocHeap> {
+    fn name(&self) -> &'static str {
+        "kmalloc"
+    }
 
-unsafe impl GlobalAlloc for Locked<KernelGlobalAlloc> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let _ = self.lock();
+        let result = if KmallocHeap::uses_pages(&layout) {
+            page_allocator::alloc(layout.size().div_ceil(get_page_align()).max(1))
+        } else {
+            let _guard = self.lock();
+            kmalloc_aligned(layout.size().max(1), layout.align())
+        };
+
+        result.unwrap_or(ptr::null_mut())
+    }
 
-        kzmalloc(layout.size()).expect("out of memory")
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if KmallocHeap::uses_pages(&layout) {
+            page_allocator::dealloc(ptr);
+        } else {
+            let _guard = self.lock();
+            kfree(ptr);
+        }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        let _ = self.lock();
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let resized = if KmallocHeap::uses_pages(&layout) {
+            layout.size().div_ceil(get_page_align()) == new_size.div_ceil(get_page_align())
+        } else {
+            let _guard = self.lock();
+            krealloc_in_place(ptr, new_size)
+        };
 
-        kfree(ptr);
+        if resized {
+            return ptr;
+        }
+
+        realloc_by_moving(self, ptr, layout, new_size)
     }
 }
 
-#[global_allocator]
-static KERNEL_GLOBAL_ALLOC: Locked<KernelGlobalAlloc> = Locked::new(KernelGlobalAlloc);
+/// Heap that serves small requests from the generic slab caches
+/// and everything else from the [`KmallocHeap`].
+pub struct SlabHeap;
+
+pub static SLAB_HEAP: SlabHeap = SlabHeap;
+
+impl KernelHeap for SlabHeap {
+    fn name(&self) -> &'static str {
+        "slab"
+    }
+
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match slab::generic_cache(layout.size(), layout.align()) {
+            Some(cache) => slab::alloc_generic(cache).unwrap_or(ptr::null_mut()),
+            None => KMALLOC_HEAP.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match slab::generic_cache(layout.size(), layout.align()) {
+            Some(cache) => slab::free_generic(cache, ptr),
+            None => KMALLOC_HEAP.dealloc(ptr, layout),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // The memory has to stay where a request of the new size is served from.
+        match (
+            slab::generic_cache(layout.size(), layout.align()),
+            slab::generic_cache(new_size, layout.align()),
+        ) {
+            (Some(old), Some(new)) if ptr::eq(old, new) => ptr,
+            (None, None) => KMALLOC_HEAP.realloc(ptr, layout, new_size),
+            _ => realloc_by_moving(self, ptr, layout, new_size),
+        }
+    }
+}
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
@@ -2,11 +2,10 @@
 #[derive(Debug, Clone, Copy)]
 pub enum PageBits {
     Empty = 0,
+    /// First page of an allocated run.
     Taken = 1 << 0,
-    Last = 1 << 1,
-
-    // Common combinations
-    TakenLast = 1 << 0 | 1 << 1,
+    /// First page of a free block.
+    Free = 1 << 1,
 }
 
 impl PageBits {
@@ -15,15 +14,27 @@ impl PageBits {
     }
 }
 
+/// Marks the end of a free list.
+pub const NO_PAGE: u32 = u32::MAX;
+
+/// Descriptor of a single page.
+/// Only the descriptor of the first page of a block or run is meaningful.
 #[derive(Debug, Clone, Copy)]
 pub struct Page {
     pub flags: u8,
+    /// Order of the free block starting at this page.
+    pub order: u8,
+    /// Number of pages of the allocated run starting at this page.
+    pub run: u32,
+    /// Next block in the free list of the same order.
+    pub next: u32,
+    /// Previous block in the free list of the same order.
+    pub prev: u32,
 }
 
 impl Page {
-    /// Check if the first bit (Taken bit) of the page is set to 1.
-    pub fn is_last(&self) -> bool {
-        self.flags & PageBits::Last.bits() != 0
+    pub const fn empty() -> Self {
+        Self { flags: PageBits::Empty as u8, order: 0, run: 0, next: NO_PAGE, prev: NO_PAGE }
     }
 
     /// Check if the first bit (Taken bit) of the page is set to 1.
@@ -31,14 +42,14 @@ impl Page {
         self.flags & PageBits::Taken.bits() != 0
     }
 
-    /// Check if the first bit (Taken bit) of the page is set to 0.
+    /// Check if the second bit (Free bit) of the page is set to 1.
     pub fn is_free(&self) -> bool {
-        !self.is_taken()
+        self.flags & PageBits::Free.bits() != 0
     }
 
-    /// Clears all flags of the page.
+    /// Clears all fields of the page.
     pub fn clear(&mut self) {
-        self.flags = PageBits::Empty.bits();
+        *self = Self::empty();
     }
 
     /// Sets the specified flags of the page.
@@ -50,4 +61,4 @@ impl Page {
     pub fn clear_flag(&mut self, flag: PageBits) {
         self.flags &= !flag.bits();
     }
-}
\ No newline at end of file
+}
@@ -1,12 +1,40 @@
 use core::intrinsics::size_of;
 use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
 use crate::allocator::align_up;
 use crate::arch::consts::{get_heap_size, get_heap_start, get_page_align};
-use crate::arch::rv64::memory::page::{Page, PageBits};
+use crate::arch::rv64::memory::buddy::BuddyAllocator;
+use crate::arch::rv64::memory::fragmentation;
+use crate::arch::rv64::memory::page::Page;
+use crate::arch::trap::without_interrupts;
 
 /// [`get_page_align`] aligned pointer to the start of the heap.
 static mut ALLOC_START: usize = 0;
 static mut IS_INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// Owns the page descriptors. Only taken with interrupts disabled.
+static ALLOCATOR: Mutex<BuddyAllocator> = Mutex::new(BuddyAllocator::empty());
+
+/// Usage of the page allocator.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageStats {
+    pub total_pages: usize,
+    pub free_pages: usize,
+    pub used_pages: usize,
+    /// Length in pages of the longest run of free pages.
+    pub largest_free_run: usize,
+    /// Successful allocations since boot.
+    pub allocations: u64,
+    /// Frees since boot.
+    pub frees: u64,
+}
+
+impl PageStats {
+    /// Share of the free pages outside of the largest free run, in parts per million.
+    /// 0 means all free pages are contiguous.
+    pub fn fragmentation(&self) -> u64 {
+        fragmentation(self.largest_free_run, self.free_pages)
+    }
+}
 
 /// Check if the page allocator is initialized.
 #[inline(always)]
@@ -24,27 +52,39 @@ pub fn init() {
         assert!(!is_initialized(), "the page allocator has already been initialized");
 
         let max_pages = get_heap_size() / get_page_align();
-        let ptr = get_heap_start() as *mut Page;
-
-        for i in 0..max_pages {
-            (*ptr.add(i)).clear();
+        let heap_end = get_heap_start() + get_heap_size();
 
-            if (*ptr.add(i)).flags != 0 {
-                (*ptr.add(i)).clear();
-
-                if (*ptr.add(i)).flags != 0 {
-                    panic!("Failed to clear page info at index {}", i);
-                }
-            }
-        }
-
-        // This is needed because the first page is used for the page descriptor.
+        // This is needed because the first pages are used for the page descriptors.
         // After that come the actual pages.
         ALLOC_START = align_up(get_heap_start() + max_pages * size_of::<Page>(), get_page_align());
+        let pages = (heap_end - ALLOC_START) / get_page_align();
+
+        *ALLOCATOR.lock() = BuddyAllocator::new(get_heap_start() as *mut Page, ALLOC_START, pages);
         IS_INITIALIZED.store(true, Ordering::Release);
     }
 }
 
+/// Returns the number of pages managed by the allocator.
+pub fn total_pages() -> usize {
+    without_interrupts(|| ALLOCATOR.lock().total_pages())
+}
+
+/// Returns the current usage of the page allocator.
+pub fn stats() -> PageStats {
+    without_interrupts(|| {
+        let allocator = ALLOCATOR.lock();
+
+        PageStats {
+            total_pages: allocator.total_pages(),
+            free_pages: allocator.free_pages(),
+            used_pages: allocator.total_pages() - allocator.free_pages(),
+            largest_free_run: allocator.largest_free_run(),
+            allocations: allocator.allocations(),
+            frees: allocator.frees(),
+        }
+    })
+}
+
 /// Allocates a number of pages.
 /// Returns a pointer to the first page.
 /// If no suitable pages are found, it returns None.
@@ -56,60 +96,19 @@ pub fn alloc(pages: usize) -> Option<*mut u8> {
     assert!(is_initialized(), "the page allocator was not initialized");
     assert!(pages > 0);
 
-    unsafe {
-        let max_pages = get_heap_size() / get_page_align();
-        let ptr = get_heap_start() as *mut Page;
-
-        for i in 0..=max_pages - pages {
-            let mut found = false;
-
-            // Check if the current page is free.
-            // If it is, we found the first candidate.
-            if (*ptr.add(i)).is_free() {
-                found = true;
-                // Check if enough consecutive pages are free.
-                for j in i..i + pages {
-                    // If the page is taken, we break the loop
-                    // and start searching for a new candidate.
-                    if (*ptr.add(j)).is_taken() {
-                        found = false;
-                        break;
-                    }
-                }
-            }
-
-            if found {
-                for j in i..i + pages - 1 {
-                    (*ptr.add(j)).set_flag(PageBits::Taken);
-                }
-
-                // Mark the last page as taken and last.
-                (*ptr.add(i + pages - 1)).set_flag(PageBits::TakenLast);
-
-                // The page structures aren't useful on their own.
-                // Instead, we convert the index to a pointer to the first byte of the page.
-                return Some((ALLOC_START + get_page_align() * i) as *mut u8);
-            }
-        }
-    }
-
-    None
+    without_interrupts(|| ALLOCATOR.lock().alloc(pages))
 }
 
-/// Frees a number of pages.
+/// Allocates a number of zeroed pages.
 /// # Safety
 /// This function must only be called after the memory has been initialized.
 /// The number of pages must be greater than 0.
 /// If either of these conditions is not met, the function will panic.
 pub fn zalloc(pages: usize) -> Option<*mut u8> {
     let ptr = alloc(pages)?;
-    let size = get_page_align() * pages;
-    let big_ptr = ptr as *mut Page;
 
-    for i in 0..size {
-        unsafe {
-            (*big_ptr.add(i)).clear();
-        }
+    unsafe {
+        ptr.write_bytes(0, get_page_align() * pages);
     }
 
     Some(ptr)
@@ -124,84 +123,42 @@ pub fn dealloc(ptr: *mut u8) {
     assert!(is_initialized(), "the page allocator was not initialized");
     assert!(!ptr.is_null(), "can not deallocate a null pointer");
 
-    unsafe {
-        let addr = get_heap_start() + (ptr as usize - ALLOC_START) / get_page_align();
-
-        assert!(addr >= get_heap_start() && addr < get_heap_start() + get_heap_size(), "pointer is out of bounds");
-
-        let mut p = addr as *mut Page;
-
-        while (*p).is_taken() && !(*p).is_last() {
-            (*p).clear();
-            p = p.add(1);
-        }
-
-        assert_eq!((*p).is_last(), true, "possible double-free detected");
-
-        // If we get here, all previous pages were taken
-        // and the last page is the only one left.
-        (*p).clear();
-    }
+    without_interrupts(|| ALLOCATOR.lock().dealloc(ptr))
 }
 
-
 /// Print all page allocations
 /// This is mainly used for debugging.
 pub fn print_page_allocations() {
-    unsafe {
-        let num_pages = get_heap_size() / get_page_align();
-        let mut beg = get_heap_start() as *const Page;
-        let end = beg.add(num_pages);
-        let alloc_beg = ALLOC_START;
-        let alloc_end = ALLOC_START + num_pages * get_page_align();
-        println!();
-        println!(
-            "PAGE ALLOCATION TABLE\nMETA: {:p} -> {:p}\nPHYS: \
-					0x{:x} -> 0x{:x}",
-            beg, end, alloc_beg, alloc_end
-        );
-        println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
-        let mut num = 0;
-        while beg < end {
-            if (*beg).is_taken() {
-                let start = beg as usize;
-                let memaddr = ALLOC_START
-                    + (start - get_heap_start())
-                    * get_page_align();
-                print!("0x{:x} => ", memaddr);
-                loop {
-                    num += 1;
-                    if (*beg).is_last() {
-                        let end = beg as usize;
-                        let memaddr = ALLOC_START
-                            + (end
-                            - get_heap_start())
-                            * get_page_align()
-                            + get_page_align() - 1;
-                        print!(
-                            "0x{:x}: {:>3} page(s)",
-                            memaddr,
-                            (end - start + 1)
-                        );
-                        println!(".");
-                        break;
-                    }
-                    beg = beg.add(1);
-                }
-            }
-            beg = beg.add(1);
-        }
-        println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
-        println!(
-            "Allocated: {:>6} pages ({:>10} bytes).",
-            num,
-            num * get_page_align()
-        );
-        println!(
-            "Free     : {:>6} pages ({:>10} bytes).",
-            num_pages - num,
-            (num_pages - num) * get_page_align()
-        );
-        println!();
-    }
-}
\ No newline at end of file
+    without_interrupts(|| print_table(&ALLOCATOR.lock()));
+}
+
+fn print_table(allocator: &BuddyAllocator) {
+    let num_pages = allocator.total_pages();
+    let beg = get_heap_start() as *const Page;
+    let end = unsafe { beg.add(num_pages) };
+    let alloc_beg = allocator.base();
+    let alloc_end = alloc_beg + num_pages * get_page_align();
+    println!();
+    println!(
+        "PAGE ALLOCATION TABLE\nMETA: {:p} -> {:p}\nPHYS: \
+                0x{:x} -> 0x{:x}",
+        beg, end, alloc_beg, alloc_end
+    );
+    println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
+    allocator.for_each_run(|addr, pages| {
+        println!("0x{:x} => 0x{:x}: {:>3} page(s).", addr, addr + pages * get_page_align() - 1, pages);
+    });
+    let num = num_pages - allocator.free_pages();
+    println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
+    println!(
+        "Allocated: {:>6} pages ({:>10} bytes).",
+        num,
+        num * get_page_align()
+    );
+    println!(
+        "Free     : {:>6} pages ({:>10} bytes).",
+        num_pages - num,
+        (num_pages - num) * get_page_align()
+    );
+    println!();
+}
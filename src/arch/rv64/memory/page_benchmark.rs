@@ -0,0 +1,136 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+use crate::arch::consts::get_page_align;
+use crate::arch::rv64::memory::buddy::BuddyAllocator;
+use crate::arch::rv64::memory::page::Page;
+use crate::arch::rv64::memory::page_allocator;
+use crate::time::{Duration, Instant};
+
+/// Operations every allocator runs.
+const OPERATIONS: usize = 20_000;
+/// Most runs alive at the same time.
+const MAX_LIVE: usize = 512;
+/// Fake address of the first page of the scratch allocators. No page is touched.
+const SCRATCH_BASE: usize = 0x1_0000_0000;
+
+/// The page allocator before the buddy allocator. It keeps one byte per page
+/// and searches for a run of free pages from the start on every allocation.
+struct LinearScan {
+    flags: *mut u8,
+    pages: usize,
+}
+
+impl LinearScan {
+    const TAKEN: u8 = 1 << 0;
+    const LAST: u8 = 1 << 1;
+
+    fn alloc(&mut self, pages: usize) -> Option<*mut u8> {
+        let flags = unsafe { core::slice::from_raw_parts_mut(self.flags, self.pages) };
+
+        let mut i = 0;
+        while i + pages <= self.pages {
+            match flags[i..i + pages].iter().position(|flag| flag & Self::TAKEN != 0) {
+                Some(taken) => i += taken + 1,
+                None => {
+                    flags[i..i + pages - 1].fill(Self::TAKEN);
+                    flags[i + pages - 1] = Self::TAKEN | Self::LAST;
+                    return Some((SCRATCH_BASE + i * get_page_align()) as *mut u8);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8) {
+        let flags = unsafe { core::slice::from_raw_parts_mut(self.flags, self.pages) };
+        let mut i = (ptr as usize - SCRATCH_BASE) / get_page_align();
+
+        while flags[i] & Self::LAST == 0 {
+            flags[i] = 0;
+            i += 1;
+        }
+
+        flags[i] = 0;
+    }
+}
+
+/// Result of running the workload on one allocator.
+struct Run {
+    elapsed: Duration,
+    failed: usize,
+}
+
+/// Runs the same random mix of allocations and frees on an allocator and times it.
+fn measure<T>(allocator: &mut T, alloc: fn(&mut T, usize) -> Option<*mut u8>, dealloc: fn(&mut T, *mut u8)) -> Run {
+    let mut live: Vec<*mut u8> = Vec::with_capacity(MAX_LIVE);
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize
+    };
+
+    let mut failed = 0;
+    let start = Instant::now();
+
+    for _ in 0..OPERATIONS {
+        let value = random();
+        if live.is_empty() || (value % 3 != 0 && live.len() < MAX_LIVE) {
+            // Mostly small runs, sometimes a larger one.
+            let pages = if value % 16 == 0 { 32 + value % 97 } else { 1 + value % 8 };
+            match alloc(allocator, pages) {
+                Some(ptr) => live.push(ptr),
+                None => failed += 1,
+            }
+        } else {
+            let ptr = live.swap_remove(value % live.len());
+            dealloc(allocator, ptr);
+        }
+    }
+
+    let elapsed = start.elapsed();
+    for ptr in live {
+        dealloc(allocator, ptr);
+    }
+
+    Run { elapsed, failed }
+}
+
+fn print_run(name: &str, run: &Run) {
+    println!(
+        "| {:<12} {:?} total, {} ns per operation, {} failed allocations",
+        name,
+        run.elapsed,
+        run.elapsed.as_nanos() / OPERATIONS as u128,
+        run.failed
+    );
+}
+
+/// Compare the buddy allocator with the old linear scan.
+///
+/// Both manage scratch descriptors for as many pages as the page allocator
+/// and run the same workload. The pages themselves are never accessed.
+pub fn run() {
+    let pages = page_allocator::total_pages();
+    let descriptor_pages = (pages * size_of::<Page>()).div_ceil(get_page_align());
+    let flag_pages = pages.div_ceil(get_page_align());
+
+    let descriptors = page_allocator::alloc(descriptor_pages).expect("out of memory for benchmark");
+    let flags = page_allocator::zalloc(flag_pages).expect("out of memory for benchmark");
+
+    println!("+ Page allocator benchmark ({} pages, {} operations):", pages, OPERATIONS);
+
+    let mut linear = LinearScan { flags, pages };
+    let result = measure(&mut linear, LinearScan::alloc, LinearScan::dealloc);
+    print_run("linear scan", &result);
+
+    let mut buddy = unsafe { BuddyAllocator::new(descriptors as *mut Page, SCRATCH_BASE, pages) };
+    let result = measure(&mut buddy, BuddyAllocator::alloc, BuddyAllocator::dealloc);
+    print_run("buddy", &result);
+    assert_eq!(buddy.free_pages(), pages, "the buddy allocator lost pages");
+
+    page_allocator::dealloc(flags);
+    page_allocator::dealloc(descriptors);
+}
@@ -0,0 +1,475 @@
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use spin::Mutex;
+use crate::allocator::align_up;
+use crate::arch::consts::{get_page_align, MAX_HARTS};
+use crate::arch::rv64::memory::page_allocator;
+use crate::arch::trap::without_interrupts;
+use crate::smp::PerHart;
+
+/// Objects a magazine holds at most.
+const MAGAZINE_SIZE: usize = 32;
+/// Empty slabs a cache keeps instead of giving them back to the page allocator.
+const MAX_EMPTY_SLABS: usize = 1;
+/// Objects a slab holds at most, limited by the size of its bitmap.
+const MAX_OBJECTS: usize = 512;
+
+/// Sizes of the generic caches used by the global allocator.
+pub const SIZE_CLASSES: [usize; 7] = [8, 16, 32, 64, 128, 256, 512];
+
+/// Caches backing small allocations of the global allocator, one for every size class.
+static GENERIC_CACHES: [Cache; SIZE_CLASSES.len()] = [
+    Cache::new("kmalloc-8", 8, 8),
+    Cache::new("kmalloc-16", 16, 16),
+    Cache::new("kmalloc-32", 32, 32),
+    Cache::new("kmalloc-64", 64, 64),
+    Cache::new("kmalloc-128", 128, 128),
+    Cache::new("kmalloc-256", 256, 256),
+    Cache::new("kmalloc-512", 512, 512),
+];
+
+/// All caches that allocated a slab, linked through [`Cache::next`].
+static CACHES: AtomicPtr<Cache> = AtomicPtr::new(ptr::null_mut());
+
+/// Header at the start of every slab. A slab is a single page,
+/// so the slab of an object is found by rounding its address down.
+#[repr(C)]
+struct Slab {
+    cache: *const Cache,
+    next: *mut Slab,
+    prev: *mut Slab,
+    in_use: usize,
+    /// A set bit marks an allocated object. Free objects are not linked
+    /// through their memory, so they keep the state of their constructor.
+    bitmap: [u64; MAX_OBJECTS / 64],
+}
+
+/// Doubly linked list of slabs.
+struct SlabList {
+    head: *mut Slab,
+    len: usize,
+}
+
+impl SlabList {
+    const fn new() -> Self {
+        Self { head: ptr::null_mut(), len: 0 }
+    }
+
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = slab;
+        }
+
+        self.head = slab;
+        self.len += 1;
+    }
+
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        if (*slab).prev.is_null() {
+            self.head = (*slab).next;
+        } else {
+            (*(*slab).prev).next = (*slab).next;
+        }
+
+        if !(*slab).next.is_null() {
+            (*(*slab).next).prev = (*slab).prev;
+        }
+
+        self.len -= 1;
+    }
+}
+
+/// Slabs of a cache, sorted by how many of their objects are allocated.
+struct Slabs {
+    partial: SlabList,
+    full: SlabList,
+    empty: SlabList,
+    /// Objects taken from slabs, including the ones held by magazines.
+    objects: usize,
+}
+
+/// Objects cached by a single hart, so most allocations and frees
+/// do not touch the shared slab lists.
+struct Magazine {
+    objects: [*mut u8; MAGAZINE_SIZE],
+    count: usize,
+}
+
+// The objects are owned by the magazine until they are handed out.
+unsafe impl Send for Magazine {}
+
+// The slabs are owned by the cache and only accessed with the lock held.
+unsafe impl Send for Slabs {}
+
+/// Untyped object cache. See [`KmemCache`].
+pub struct Cache {
+    name: &'static str,
+    /// Size of an object, a multiple of its alignment.
+    size: usize,
+    align: usize,
+    slabs: Mutex<Slabs>,
+    magazines: PerHart<Mutex<Magazine>>,
+    registered: AtomicBool,
+    next: AtomicPtr<Cache>,
+    allocations: AtomicU64,
+    frees: AtomicU64,
+}
+
+/// Statistics of an object cache.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub partial_slabs: usize,
+    pub full_slabs: usize,
+    pub empty_slabs: usize,
+    /// Objects allocated by users of the cache.
+    pub objects_in_use: usize,
+    /// Free objects held by the magazines of all harts.
+    pub objects_cached: usize,
+    /// Successful allocations since boot.
+    pub allocations: u64,
+    /// Frees since boot.
+    pub frees: u64,
+}
+
+impl Cache {
+    const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        let size = if size == 0 { align } else { (size + align - 1) & !(align - 1) };
+
+        Self {
+            name,
+            size,
+            align,
+            slabs: Mutex::new(Slabs {
+                partial: SlabList::new(),
+                full: SlabList::new(),
+                empty: SlabList::new(),
+                objects: 0,
+            }),
+            magazines: PerHart::new(
+                [const { Mutex::new(Magazine { objects: [ptr::null_mut(); MAGAZINE_SIZE], count: 0 }) }; MAX_HARTS],
+            ),
+            registered: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+            allocations: AtomicU64::new(0),
+            frees: AtomicU64::new(0),
+        }
+    }
+
+    /// Offset of the first object in a slab.
+    fn offset(&self) -> usize {
+        align_up(size_of::<Slab>(), self.align)
+    }
+
+    fn objects_per_slab(&self) -> usize {
+        ((get_page_align() - self.offset()) / self.size).min(MAX_OBJECTS)
+    }
+
+    /// Allocates an object. `construct` is called on every object of a new slab.
+    fn alloc(&'static self, construct: &dyn Fn(*mut u8)) -> Option<*mut u8> {
+        let object = without_interrupts(|| {
+            let mut magazine = self.magazines.get().lock();
+            if magazine.count == 0 {
+                self.refill(&mut magazine, construct);
+            }
+
+            if magazine.count == 0 {
+                return None;
+            }
+
+            magazine.count -= 1;
+            Some(magazine.objects[magazine.count])
+        })?;
+
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        Some(object)
+    }
+
+    /// Frees an object of this cache.
+    /// # Safety
+    /// The pointer must have been returned by [`Cache::alloc`] of this cache.
+    /// Otherwise the function will panic.
+    fn free(&self, object: *mut u8) {
+        let slab = (object as usize & !(get_page_align() - 1)) as *const Slab;
+        assert!(unsafe { (*slab).cache } == self as *const Cache, "object does not belong to cache {}", self.name);
+
+        without_interrupts(|| {
+            let mut magazine = self.magazines.get().lock();
+            if magazine.count == MAGAZINE_SIZE {
+                self.flush(&mut magazine);
+            }
+
+            let count = magazine.count;
+            magazine.objects[count] = object;
+            magazine.count += 1;
+        });
+
+        self.frees.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fill half of an empty magazine from the slabs.
+    fn refill(&'static self, magazine: &mut Magazine, construct: &dyn Fn(*mut u8)) {
+        let mut slabs = self.slabs.lock();
+
+        unsafe {
+            while magazine.count < MAGAZINE_SIZE / 2 {
+                let slab = if !slabs.partial.head.is_null() {
+                    slabs.partial.head
+                } else if !slabs.empty.head.is_null() {
+                    let slab = slabs.empty.head;
+                    slabs.empty.remove(slab);
+                    slabs.partial.push(slab);
+                    slab
+                } else {
+                    match self.grow(construct) {
+                        Some(slab) => {
+                            slabs.partial.push(slab);
+                            slab
+                        }
+                        None => break,
+                    }
+                };
+
+                magazine.objects[magazine.count] = self.take(slab);
+                magazine.count += 1;
+                slabs.objects += 1;
+
+                if (*slab).in_use == self.objects_per_slab() {
+                    slabs.partial.remove(slab);
+                    slabs.full.push(slab);
+                }
+            }
+        }
+    }
+
+    /// Give half of a full magazine back to the slabs.
+    fn flush(&self, magazine: &mut Magazine) {
+        let mut slabs = self.slabs.lock();
+
+        unsafe {
+            while magazine.count > MAGAZINE_SIZE / 2 {
+                magazine.count -= 1;
+                let object = magazine.objects[magazine.count];
+                let slab = (object as usize & !(get_page_align() - 1)) as *mut Slab;
+
+                if (*slab).in_use == self.objects_per_slab() {
+                    slabs.full.remove(slab);
+                    slabs.partial.push(slab);
+                }
+
+                self.put(slab, object);
+                slabs.objects -= 1;
+
+                if (*slab).in_use == 0 {
+                    slabs.partial.remove(slab);
+                    if slabs.empty.len < MAX_EMPTY_SLABS {
+                        slabs.empty.push(slab);
+                    } else {
+                        page_allocator::dealloc(slab as *mut u8);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Allocates a new slab and constructs all of its objects.
+    fn grow(&'static self, construct: &dyn Fn(*mut u8)) -> Option<*mut Slab> {
+        assert!(self.objects_per_slab() > 0, "objects of cache {} do not fit into a slab", self.name);
+
+        let slab = page_allocator::alloc(1)? as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                cache: self,
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                in_use: 0,
+                bitmap: [0; MAX_OBJECTS / 64],
+            });
+
+            for index in 0..self.objects_per_slab() {
+                construct(self.object(slab, index));
+            }
+        }
+
+        self.register();
+        Some(slab)
+    }
+
+    /// Add the cache to the list printed by [`print_caches`].
+    fn register(&'static self) {
+        if self.registered.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let mut head = CACHES.load(Ordering::Acquire);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match CACHES.compare_exchange_weak(head, self as *const Cache as *mut Cache, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    fn object(&self, slab: *mut Slab, index: usize) -> *mut u8 {
+        (slab as usize + self.offset() + index * self.size) as *mut u8
+    }
+
+    /// Take a free object of a slab that is not full.
+    unsafe fn take(&self, slab: *mut Slab) -> *mut u8 {
+        let bitmap = &mut (*slab).bitmap;
+        let word = bitmap.iter().position(|word| *word != u64::MAX).expect("slab is full");
+        let bit = (!bitmap[word]).trailing_zeros() as usize;
+        let index = word * 64 + bit;
+        debug_assert!(index < self.objects_per_slab());
+
+        bitmap[word] |= 1 << bit;
+        (*slab).in_use += 1;
+        self.object(slab, index)
+    }
+
+    /// Return an object to its slab.
+    unsafe fn put(&self, slab: *mut Slab, object: *mut u8) {
+        let index = (object as usize - slab as usize - self.offset()) / self.size;
+        let (word, bit) = (index / 64, index % 64);
+        assert!((*slab).bitmap[word] & (1 << bit) != 0, "possible double-free detected in cache {}", self.name);
+
+        (*slab).bitmap[word] &= !(1 << bit);
+        (*slab).in_use -= 1;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (partial_slabs, full_slabs, empty_slabs, objects) = without_interrupts(|| {
+            let slabs = self.slabs.lock();
+            (slabs.partial.len, slabs.full.len, slabs.empty.len, slabs.objects)
+        });
+        let objects_cached = self
+            .magazines
+            .iter()
+            .map(|(_, magazine)| without_interrupts(|| magazine.lock().count))
+            .sum::<usize>();
+
+        CacheStats {
+            name: self.name,
+            object_size: self.size,
+            objects_per_slab: self.objects_per_slab(),
+            partial_slabs,
+            full_slabs,
+            empty_slabs,
+            objects_in_use: objects.saturating_sub(objects_cached),
+            objects_cached,
+            allocations: self.allocations.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Cache of equally sized kernel objects of type `T`.
+///
+/// Objects are carved out of slabs, single pages from the page allocator,
+/// so allocating and freeing never walks the kmalloc list. Every hart keeps
+/// a magazine of free objects and only goes to the shared slabs when it
+/// runs empty or full.
+///
+/// An optional constructor initializes the objects once when their slab is
+/// created. Objects have to be freed in their constructed state, so they
+/// can be handed out again without running the constructor.
+pub struct KmemCache<T> {
+    cache: Cache,
+    constructor: Option<fn(&mut MaybeUninit<T>)>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> KmemCache<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            cache: Cache::new(name, size_of::<T>(), core::mem::align_of::<T>()),
+            constructor: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// A cache that runs `constructor` on every object of a new slab.
+    pub const fn with_constructor(name: &'static str, constructor: fn(&mut MaybeUninit<T>)) -> Self {
+        Self {
+            cache: Cache::new(name, size_of::<T>(), core::mem::align_of::<T>()),
+            constructor: Some(constructor),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Allocates an object. It is uninitialized unless the cache has a constructor.
+    /// Returns None if the page allocator is out of memory.
+    pub fn alloc(&'static self) -> Option<*mut T> {
+        let construct = |object: *mut u8| {
+            if let Some(constructor) = self.constructor {
+                constructor(unsafe { &mut *(object as *mut MaybeUninit<T>) });
+            }
+        };
+
+        self.cache.alloc(&construct).map(|object| object as *mut T)
+    }
+
+    /// Frees an object allocated by [`KmemCache::alloc`].
+    /// # Safety
+    /// The pointer must have been returned by this cache.
+    /// Otherwise the function will panic.
+    pub fn free(&self, object: *mut T) {
+        self.cache.free(object as *mut u8);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+}
+
+/// Returns the generic cache for allocations of the given size and alignment,
+/// or None if they are too large for every size class.
+pub fn generic_cache(size: usize, align: usize) -> Option<&'static Cache> {
+    let size = size.max(align);
+    SIZE_CLASSES
+        .iter()
+        .position(|&class| size <= class)
+        .map(|class| &GENERIC_CACHES[class])
+}
+
+/// Allocates from a generic cache.
+pub fn alloc_generic(cache: &'static Cache) -> Option<*mut u8> {
+    cache.alloc(&|_| {})
+}
+
+/// Frees an object allocated by [`alloc_generic`].
+pub fn free_generic(cache: &'static Cache, object: *mut u8) {
+    cache.free(object);
+}
+
+/// Print the statistics of all caches that allocated a slab.
+pub fn print_caches() {
+    println!("+ Slab caches:");
+
+    let mut cache = CACHES.load(Ordering::Acquire);
+    while !cache.is_null() {
+        let stats = unsafe { (*cache).stats() };
+        println!(
+            "| {:<16} {:>4} bytes, {:>3} per slab, {}/{}/{} partial/full/empty slabs, {} in use, {} cached, {} allocs, {} frees",
+            stats.name,
+            stats.object_size,
+            stats.objects_per_slab,
+            stats.partial_slabs,
+            stats.full_slabs,
+            stats.empty_slabs,
+            stats.objects_in_use,
+            stats.objects_cached,
+            stats.allocations,
+            stats.frees
+        );
+
+        cache = unsafe { (*cache).next.load(Ordering::Acquire) };
+    }
+}
@@ -5,5 +5,12 @@ pub mod logger;
 pub mod consts;
 pub mod trap;
 pub mod paging_sv39;
-mod memory;
+pub mod backtrace;
+pub mod stack;
+pub mod plic;
+pub mod ipi;
+pub mod context;
+pub mod memory;
 mod asm;
+
+pub use asm::{hart_id, get_time, write_stimecmp};
@@ -1,12 +1,11 @@
-pub mod entry;
-pub mod table;
 pub mod mapping;
 
-pub use table::*;
-pub use entry::*;
-use crate::arch::consts::{get_bss_end, get_bss_start, get_data_end, get_data_start, get_heap_size, get_heap_start, get_page_align, get_rodata_end, get_rodata_start, get_stack_end, get_stack_start, get_text_end, get_text_start};
+pub use mm::sv39::{EntryBits, Table};
+use crate::arch::consts::{get_bss_end, get_bss_start, get_data_end, get_data_start, get_heap_size, get_heap_start, get_page_align, get_rodata_end, get_rodata_start, get_text_end, get_text_start, MAX_HARTS};
 use crate::arch::paging_sv39::mapping::id_map_range;
-use crate::arch::rv64::asm::{write_satp};
+use crate::arch::rv64::asm::{sfence_vma, write_satp};
+use crate::arch::rv64::stack::hart_stack_bounds;
+use crate::dtb;
 use crate::arch::rv64::memory::kernel_allocator;
 
 /// Initialize the virtual memory.
@@ -15,10 +14,34 @@ use crate::arch::rv64::memory::kernel_allocator;
 /// This function will panic if the kernel heap is not initialized.
 pub fn init() {
     identity_map();
+    enable();
+}
 
+/// Enable virtual memory on the current hart
+/// using the page table set up by [`init`].
+/// # Safety
+/// This function will panic if [`init`] has not been called yet.
+pub fn enable() {
     let table = kernel_allocator::get_page_table().expect("failed to get root page table");
 
     write_satp((table as usize >> 12) | (8 << 60));
+    sfence_vma();
+}
+
+/// Identity map a memory mapped device into the kernel page table,
+/// so that drivers can access it while virtual memory is enabled.
+/// # Safety
+/// This function will panic if [`init`] has not been called yet.
+pub fn id_map_mmio(start: usize, size: usize) {
+    let root_ptr = kernel_allocator::get_page_table().expect("failed to get root page table");
+    let root = unsafe { root_ptr.as_mut().expect("root is null") };
+
+    id_map_range(
+        root,
+        start,
+        start + size,
+        EntryBits::ReadWrite.bits());
+    sfence_vma();
 }
 
 /// Identity map important memory regions.
@@ -77,12 +100,26 @@ fn identity_map() {
         get_bss_end(),
         EntryBits::ReadWrite.bits());
 
-    // Map kernel stack
-    id_map_range(
-        &mut root,
-        get_stack_start(),
-        get_stack_end(),
-        EntryBits::ReadWrite.bits());
+    // Map the device tree blob, it is located behind the kernel memory.
+    if let Some(tree) = dtb::get() {
+        id_map_range(
+            &mut root,
+            tree.addr(),
+            tree.addr() + tree.size(),
+            EntryBits::Read.bits());
+    }
+
+    // Map kernel stacks
+    // The lowest page of every hart stack is left unmapped as a guard page,
+    // so an overflow faults instead of corrupting the stack below it.
+    for hart_id in 0..MAX_HARTS {
+        let (bottom, top) = hart_stack_bounds(hart_id);
+        id_map_range(
+            &mut root,
+            bottom,
+            top,
+            EntryBits::ReadWrite.bits());
+    }
 
     kernel_allocator::set_page_table(root);
 }
\ No newline at end of file
@@ -0,0 +1,291 @@
+// Documentation can be found here:
+// https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use spin::{Once, RwLock};
+use crate::arch::consts::MAX_HARTS;
+use crate::arch::paging_sv39::id_map_mmio;
+use crate::arch::rv64::asm::hart_id;
+use crate::arch::trap::{enable_external_interrupts, without_interrupts};
+use crate::dtb;
+use crate::smp::HartMask;
+
+const PRIORITY_OFFSET: usize = 0x0000;
+const PENDING_OFFSET: usize = 0x1000;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_CONTEXT_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0x0;
+const CONTEXT_CLAIM: usize = 0x4;
+
+/// Interrupt type of the hart local interrupt controller
+/// a PLIC context is connected to. 9 is the supervisor external interrupt.
+const SUPERVISOR_EXTERNAL_INTERRUPT: u32 = 9;
+
+/// The highest priority an interrupt source can have on QEMU virt.
+pub const MAX_PRIORITY: u32 = 7;
+
+pub type IrqHandler = Box<dyn Fn(u32) + Send + Sync>;
+
+static PLIC: Once<Plic> = Once::new();
+static HANDLERS: RwLock<BTreeMap<u32, IrqHandler>> = RwLock::new(BTreeMap::new());
+
+/// A platform level interrupt controller.
+#[derive(Debug)]
+pub struct Plic {
+    base: usize,
+    size: usize,
+    /// Number of interrupt sources, source 0 does not exist.
+    sources: u32,
+    /// The supervisor mode context of every hart.
+    contexts: [Option<usize>; MAX_HARTS],
+}
+
+impl Plic {
+    /// Find the PLIC in the device tree together with the
+    /// supervisor mode context that belongs to each hart.
+    fn from_device_tree(tree: &dtb::DeviceTree) -> Option<Self> {
+        let node = tree
+            .find_compatible("riscv,plic0")
+            .or_else(|| tree.find_compatible("sifive,plic-1.0.0"))?;
+        let (base, size) = node.reg().next()?;
+        let sources = node.property("riscv,ndev").and_then(|p| p.as_u32())?;
+
+        // `interrupts-extended` contains one (phandle, type) pair per context.
+        // The phandle points to the interrupt controller of a cpu node,
+        // whose `reg` property is the hart id.
+        let mut contexts = [None; MAX_HARTS];
+        let cells = node.property("interrupts-extended")?;
+        let mut cells = cells.u32s();
+        let mut context = 0;
+        while let (Some(phandle), Some(kind)) = (cells.next(), cells.next()) {
+            if kind == SUPERVISOR_EXTERNAL_INTERRUPT {
+                let hart = tree
+                    .find_phandle(phandle)
+                    .and_then(|intc| intc.parent())
+                    .and_then(|cpu| cpu.reg().next())
+                    .map(|(hart, _)| hart);
+
+                if let Some(hart) = hart.filter(|&hart| hart < MAX_HARTS) {
+                    contexts[hart] = Some(context);
+                }
+            }
+            context += 1;
+        }
+
+        Some(Self { base, size, sources, contexts })
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn context(&self, hart: usize) -> usize {
+        self.contexts
+            .get(hart)
+            .copied()
+            .flatten()
+            .unwrap_or_else(|| panic!("hart {} has no PLIC context", hart))
+    }
+
+    fn check_irq(&self, irq: u32) {
+        assert!(irq > 0 && irq <= self.sources, "invalid interrupt source {}", irq);
+    }
+
+    /// Set the priority of an interrupt source.
+    /// A priority of 0 means the interrupt never fires.
+    pub fn set_priority(&self, irq: u32, priority: u32) {
+        self.check_irq(irq);
+        assert!(priority <= MAX_PRIORITY, "invalid interrupt priority {}", priority);
+
+        unsafe {
+            self.reg(PRIORITY_OFFSET + irq as usize * 4).write_volatile(priority);
+        }
+    }
+
+    /// Returns the priority of an interrupt source.
+    pub fn priority(&self, irq: u32) -> u32 {
+        self.check_irq(irq);
+
+        unsafe { self.reg(PRIORITY_OFFSET + irq as usize * 4).read_volatile() }
+    }
+
+    /// Set the priority threshold of a hart.
+    /// Only interrupts with a priority above the threshold reach the hart.
+    pub fn set_threshold(&self, hart: usize, threshold: u32) {
+        assert!(threshold <= MAX_PRIORITY, "invalid priority threshold {}", threshold);
+        let context = self.context(hart);
+
+        unsafe {
+            self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + CONTEXT_THRESHOLD).write_volatile(threshold);
+        }
+    }
+
+    /// Check if an interrupt source is pending.
+    pub fn is_pending(&self, irq: u32) -> bool {
+        self.check_irq(irq);
+
+        let word = unsafe { self.reg(PENDING_OFFSET + (irq as usize / 32) * 4).read_volatile() };
+        word & (1 << (irq % 32)) != 0
+    }
+
+    fn enable_reg(&self, irq: u32, hart: usize) -> *mut u32 {
+        self.check_irq(irq);
+        let context = self.context(hart);
+
+        self.reg(ENABLE_OFFSET + context * ENABLE_CONTEXT_STRIDE + (irq as usize / 32) * 4)
+    }
+
+    /// Enable an interrupt source for a hart.
+    pub fn enable(&self, irq: u32, hart: usize) {
+        let reg = self.enable_reg(irq, hart);
+
+        unsafe {
+            reg.write_volatile(reg.read_volatile() | (1 << (irq % 32)));
+        }
+    }
+
+    /// Disable an interrupt source for a hart.
+    pub fn disable(&self, irq: u32, hart: usize) {
+        let reg = self.enable_reg(irq, hart);
+
+        unsafe {
+            reg.write_volatile(reg.read_volatile() & !(1 << (irq % 32)));
+        }
+    }
+
+    /// Check if an interrupt source is enabled for a hart.
+    pub fn is_enabled(&self, irq: u32, hart: usize) -> bool {
+        let reg = self.enable_reg(irq, hart);
+
+        unsafe { reg.read_volatile() & (1 << (irq % 32)) != 0 }
+    }
+
+    /// Route an interrupt source to exactly the harts in the given mask.
+    /// If multiple harts are selected, the first one
+    /// to claim the interrupt handles it.
+    pub fn route(&self, irq: u32, harts: HartMask) {
+        for hart in 0..MAX_HARTS {
+            if self.contexts[hart].is_none() {
+                continue;
+            }
+
+            if harts.contains(hart) {
+                self.enable(irq, hart);
+            } else {
+                self.disable(irq, hart);
+            }
+        }
+    }
+
+    /// Claim the highest priority pending interrupt for a hart.
+    /// Returns None if no interrupt is pending.
+    pub fn claim(&self, hart: usize) -> Option<u32> {
+        let context = self.context(hart);
+
+        let irq = unsafe { self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + CONTEXT_CLAIM).read_volatile() };
+        match irq {
+            0 => None,
+            irq => Some(irq),
+        }
+    }
+
+    /// Signal that a claimed interrupt has been handled,
+    /// which allows the source to raise it again.
+    pub fn complete(&self, hart: usize, irq: u32) {
+        let context = self.context(hart);
+
+        unsafe {
+            self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + CONTEXT_CLAIM).write_volatile(irq);
+        }
+    }
+}
+
+/// Initialize the PLIC described in the device tree.
+/// All interrupt sources start out disabled with a priority of 0.
+/// Requires virtual memory and the kernel heap to be initialized.
+pub fn init() {
+    let tree = dtb::get().expect("device tree not initialized");
+    let plic = Plic::from_device_tree(tree).expect("no PLIC found in the device tree");
+
+    id_map_mmio(plic.base, plic.size);
+
+    for irq in 1..=plic.sources {
+        plic.set_priority(irq, 0);
+        for hart in 0..MAX_HARTS {
+            if plic.contexts[hart].is_some() {
+                plic.disable(irq, hart);
+            }
+        }
+    }
+
+    println!("| PLIC at {:#x} with {} sources", plic.base, plic.sources);
+    PLIC.call_once(|| plic);
+
+    init_hart();
+}
+
+/// Prepare the current hart to receive external interrupts.
+pub fn init_hart() {
+    get().set_threshold(hart_id(), 0);
+    enable_external_interrupts();
+}
+
+/// Returns the PLIC of the system.
+/// # Safety
+/// This function will panic if [`init`] has not been called yet.
+pub fn get() -> &'static Plic {
+    PLIC.get().expect("PLIC not initialized")
+}
+
+/// Check if the PLIC has been initialized.
+pub fn is_initialized() -> bool {
+    PLIC.is_completed()
+}
+
+/// Register a handler for an interrupt source and route it to the current hart.
+/// The handler runs in trap context with interrupts disabled
+/// and must not block. It replaces any previously registered handler.
+pub fn register_handler(irq: u32, priority: u32, handler: impl Fn(u32) + Send + Sync + 'static) {
+    let plic = get();
+    plic.check_irq(irq);
+
+    without_interrupts(|| {
+        HANDLERS.write().insert(irq, Box::new(handler));
+    });
+
+    plic.set_priority(irq, priority);
+    plic.route(irq, HartMask::single(hart_id()));
+}
+
+/// Remove the handler of an interrupt source and disable it on all harts.
+pub fn unregister_handler(irq: u32) {
+    let plic = get();
+
+    plic.set_priority(irq, 0);
+    plic.route(irq, HartMask::empty());
+
+    without_interrupts(|| {
+        HANDLERS.write().remove(&irq);
+    });
+}
+
+/// Handle a supervisor external interrupt on the current hart.
+/// Claims and dispatches interrupts until no more are pending.
+pub fn handle_interrupt() {
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+    let hart = hart_id();
+
+    while let Some(irq) = plic.claim(hart) {
+        match HANDLERS.read().get(&irq) {
+            Some(handler) => handler(irq),
+            None => println!("Unhandled external interrupt {} on hart {}", irq, hart),
+        }
+
+        plic.complete(hart, irq);
+    }
+}
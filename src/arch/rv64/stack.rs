@@ -0,0 +1,158 @@
+use crate::arch::consts::{get_hart_stack_size, get_page_align, get_stack_guard_size, get_stack_start, MAX_HARTS};
+use crate::arch::rv64::asm::read_sp;
+use crate::arch::rv64::memory::page_allocator;
+
+/// Pattern the unused part of the hart stacks is painted with.
+const STACK_PAINT: usize = 0x5354_4143_4b50_4e54;
+
+/// Value at the bottom of every kernel thread stack.
+/// If it changes, the thread overflowed its stack.
+const STACK_CANARY: usize = 0x4341_4e41_5259_5354;
+
+/// Size of the per hart emergency stack used to report stack overflows.
+const TRAP_STACK_SIZE: usize = 0x2000;
+
+#[repr(C, align(16))]
+struct TrapStack([u8; TRAP_STACK_SIZE]);
+
+/// Emergency stacks the trap vector switches to
+/// when a hart overflowed its kernel stack.
+static mut TRAP_STACKS: [TrapStack; MAX_HARTS] = [const { TrapStack([0; TRAP_STACK_SIZE]) }; MAX_HARTS];
+
+/// Returns the top of the emergency trap stack of the given hart.
+pub fn trap_stack_top(hart_id: usize) -> usize {
+    assert!(hart_id < MAX_HARTS, "hart {} has no trap stack", hart_id);
+
+    unsafe { core::ptr::addr_of!(TRAP_STACKS[hart_id]) as usize + TRAP_STACK_SIZE }
+}
+
+/// Returns the usable stack region `(bottom, top)` of the given hart.
+/// The guard page below `bottom` is not mapped.
+pub fn hart_stack_bounds(hart_id: usize) -> (usize, usize) {
+    assert!(hart_id < MAX_HARTS, "hart {} has no stack", hart_id);
+
+    let base = get_stack_start() + hart_id * get_hart_stack_size();
+    (base + get_stack_guard_size(), base + get_hart_stack_size())
+}
+
+/// Returns the hart whose stack guard page contains the given address.
+pub fn guard_page_owner(addr: usize) -> Option<usize> {
+    (0..MAX_HARTS).find(|&hart_id| {
+        let (bottom, _) = hart_stack_bounds(hart_id);
+        addr >= bottom - get_stack_guard_size() && addr < bottom
+    })
+}
+
+/// Paint the unused stack of the given hart so that
+/// [`high_water_mark`] can measure its usage later on.
+///
+/// When called for the current hart, only the memory below
+/// the current stack pointer is painted.
+///
+/// # Safety
+/// The stack of another hart must not be in use.
+pub unsafe fn paint(hart_id: usize) {
+    let (bottom, top) = hart_stack_bounds(hart_id);
+    let sp = read_sp();
+
+    let end = if sp > bottom && sp <= top {
+        // Leave some room for the frame of this function.
+        sp - 64
+    } else {
+        top
+    };
+
+    let mut addr = bottom;
+    while addr < end {
+        (addr as *mut usize).write_volatile(STACK_PAINT);
+        addr += core::mem::size_of::<usize>();
+    }
+}
+
+/// Returns the maximum number of bytes the given hart ever used of its stack
+/// since it was painted. This is only an estimate, because stack frames may
+/// contain unwritten holes or happen to contain the paint pattern.
+pub fn high_water_mark(hart_id: usize) -> usize {
+    let (bottom, top) = hart_stack_bounds(hart_id);
+
+    let mut addr = bottom;
+    while addr < top {
+        if unsafe { (addr as *const usize).read_volatile() } != STACK_PAINT {
+            break;
+        }
+        addr += core::mem::size_of::<usize>();
+    }
+
+    top - addr
+}
+
+/// Print the high-water mark of every hart stack.
+pub fn print_stack_usage() {
+    let (bottom, top) = hart_stack_bounds(0);
+    let usable = top - bottom;
+
+    println!("+ Stack usage");
+    for hart_id in 0..MAX_HARTS {
+        let used = high_water_mark(hart_id);
+        println!("| Hart {}: {:#x} / {:#x} bytes ({}%)", hart_id, used, usable, used * 100 / usable);
+    }
+}
+
+/// Kernel stack of a thread, allocated from the page allocator.
+///
+/// Thread stacks have no guard page. Instead a canary is placed at the
+/// bottom of the stack, which is checked every time the thread is switched out.
+pub struct KernelStack {
+    bottom: *mut u8,
+    pages: usize,
+}
+
+// The stack is only a region of memory, it is owned by a single thread.
+unsafe impl Send for KernelStack {}
+unsafe impl Sync for KernelStack {}
+
+impl KernelStack {
+    /// Allocate a stack of the given number of pages.
+    /// Returns None if the page allocator is out of memory.
+    pub fn new(pages: usize) -> Option<Self> {
+        let bottom = page_allocator::alloc(pages)?;
+        unsafe {
+            (bottom as *mut usize).write_volatile(STACK_CANARY);
+        }
+
+        Some(Self { bottom, pages })
+    }
+
+    /// Returns the initial stack pointer.
+    pub fn top(&self) -> usize {
+        self.bottom as usize + self.pages * get_page_align()
+    }
+
+    /// Check if the canary at the bottom of the stack is still intact.
+    pub fn is_intact(&self) -> bool {
+        unsafe { (self.bottom as *const usize).read_volatile() == STACK_CANARY }
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        page_allocator::dealloc(self.bottom);
+    }
+}
+
+/// Called by the trap vector on the emergency stack
+/// when a hart overflowed its kernel stack.
+#[no_mangle]
+extern "C" fn stack_overflow_handler(hart_id: usize, sp: usize, epc: usize, tval: usize) -> ! {
+    let (bottom, top) = hart_stack_bounds(hart_id);
+
+    println!("Kernel stack overflow on hart {}", hart_id);
+    println!("| sp: {:#x} (stack {:#x} - {:#x})", sp, bottom, top);
+    println!("| sepc: {:#x}", epc);
+    println!("| stval: {:#x}", tval);
+    if let Some(owner) = guard_page_owner(tval) {
+        println!("| Faulting address is in the guard page of hart {}", owner);
+    }
+
+    panic!("kernel stack overflow on hart {}", hart_id);
+}
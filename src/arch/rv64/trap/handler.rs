@@ -1,10 +1,15 @@
 use core::arch::asm;
-use opensbi::time::set_timer;
-use crate::arch::rv64::asm::get_time;
-use crate::arch::trap::{clear_timer_interrupt, extract_scause, get_interrupt_cause, read_sepc};
+use crate::arch::backtrace::{frame_pointer, interrupted_frame_pointer};
+use crate::arch::plic;
+use crate::arch::stack::guard_page_owner;
+use crate::{smp, thread, time};
+use crate::arch::trap::{InterruptedContext, extract_scause, get_interrupt_cause, read_sepc};
 
 #[no_mangle]
 pub extern "riscv-interrupt-s" fn s_mode_trap_handler() {
+    // The frame pointer of the handler itself. The frame record it points to
+    // links to the interrupted code, which is needed for backtraces.
+    let fp = frame_pointer();
     let (is_async, cause) = extract_scause(get_interrupt_cause());
     let epc = read_sepc();
     let stval = {
@@ -16,9 +21,9 @@ pub extern "riscv-interrupt-s" fn s_mode_trap_handler() {
     };
 
     let return_pc = if is_async {
-        s_mode_async_handler(cause, epc, stval)
+        s_mode_async_handler(cause, epc, stval, fp)
     } else {
-        s_mode_sync_handler(cause, epc, stval)
+        s_mode_sync_handler(cause, epc, stval, fp)
     };
 
     unsafe {
@@ -31,13 +36,17 @@ fn s_mode_async_handler(
     cause: usize,
     epc: usize,
     _tval: usize,
+    fp: usize,
 ) -> usize {
     let mut return_pc = epc;
 
     match cause {
         1 => {
-            // Supervisor software
-            println!("Supervisor software interrupt");
+            // Supervisor software (inter-processor interrupt)
+            if crate::panic::stop_requested() {
+                crate::panic::park(Some(&InterruptedContext::from_handler_frame(epc, fp)));
+            }
+            smp::handle_ipi();
         },
         3 => {
             // Machine software
@@ -45,22 +54,20 @@ fn s_mode_async_handler(
         },
         5 => {
             // Supervisor timer
-            println!("Supervisor timer interrupt");
-            clear_timer_interrupt();
-            set_timer(get_time() + 10_000_000);
+            time::timer::handle_timer_interrupt();
         },
         7 => unsafe {
             // Machine timer
             println!("Machine timer interrupt");
             let mtimecmp = 0x0200_4000 as *mut u64;
             let mtime = 0x0200_bff8 as *const u64;
-            // The frequency given by QEMU is 10_000_000 Hz, so this sets
-            // the next interrupt to fire one second from now.
-            mtimecmp.write_volatile(mtime.read_volatile() + 10_000_000);
+            // Fire again one tick period from now.
+            let period = time::duration_to_ticks(time::timer::TICK_PERIOD);
+            mtimecmp.write_volatile(mtime.read_volatile() + period);
         },
         9 => {
             // Supervisor external (interrupt from Platform Interrupt Controller (PLIC))
-            println!("Supervisor external interrupt");
+            plic::handle_interrupt();
         },
         11 => {
             // Machine external (interrupt from Platform Interrupt Controller (PLIC))
@@ -71,6 +78,10 @@ fn s_mode_async_handler(
         }
     }
 
+    // May switch to another thread. The trap returns once
+    // the interrupted thread is switched back in.
+    thread::scheduler::preempt();
+
     return_pc
 }
 
@@ -79,12 +90,14 @@ fn s_mode_sync_handler(
     cause: usize,
     epc: usize,
     tval: usize,
+    fp: usize,
 ) -> usize {
     let mut return_pc = epc;
 
     match cause {
         2 => {
             // Illegal instruction
+            print_trap_backtrace(epc, fp);
             panic!("Illegal instruction -> {:#x}: {:#x}", epc, tval);
         },
         8 => {
@@ -99,9 +112,16 @@ fn s_mode_sync_handler(
         },
         11 => {
             // Environment (system) call from Machine mode
+            print_trap_backtrace(epc, fp);
             panic!("E-call from Machine mode -> {:#x}", epc);
         },
         // Page faults
+        12 | 13 | 15 if guard_page_owner(tval).is_some() => {
+            // Access to a stack guard page that did not come from the stack
+            // pointer itself, for example a large stack frame skipping over it.
+            print_trap_backtrace(epc, fp);
+            panic!("Stack guard page of hart {} hit -> {:#x}: {:#x}", guard_page_owner(tval).unwrap(), epc, tval);
+        },
         12 => {
             // Instruction page fault
             println!("Instruction page fault -> {:#x}: {:#x}", epc, tval);
@@ -118,9 +138,18 @@ fn s_mode_sync_handler(
             return_pc += 4;
         },
         _ => {
+            print_trap_backtrace(epc, fp);
             panic!("Unhandled sync trap -> {}", cause);
         }
     }
 
     return_pc
+}
+
+/// Print a backtrace starting at the trapping instruction.
+/// Used for fatal traps, since the backtrace printed by the panic
+/// handler does not include the function the trap occurred in.
+fn print_trap_backtrace(epc: usize, handler_fp: usize) {
+    println!("Fatal trap at {:#x}", epc);
+    crate::backtrace::print_from(epc, interrupted_frame_pointer(handler_fp));
 }
\ No newline at end of file
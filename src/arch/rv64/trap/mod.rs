@@ -2,7 +2,34 @@ mod handler;
 
 use core::arch::asm;
 use core::arch::riscv64::wfi;
-use crate::arch::trap::handler::s_mode_trap_handler;
+use crate::arch::backtrace::frame_record;
+use crate::arch::rv64::asm::hart_id;
+use crate::arch::rv64::stack::trap_stack_top;
+
+extern "C" {
+    /// The trap vector defined in `asm/trap.S`.
+    fn _s_trap_vector();
+}
+
+/// Registers of the code that was interrupted by a trap.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptedContext {
+    pub epc: usize,
+    pub sp: usize,
+    pub ra: usize,
+    pub fp: usize,
+}
+
+impl InterruptedContext {
+    /// Recover the interrupted registers from the frame of the trap handler.
+    /// The handler saves `ra` and `s0` in its frame record and its frame
+    /// pointer is the stack pointer at the time of the trap.
+    pub fn from_handler_frame(epc: usize, handler_fp: usize) -> Self {
+        let (ra, fp) = frame_record(handler_fp).unwrap_or((0, 0));
+
+        Self { epc, sp: handler_fp, ra, fp }
+    }
+}
 
 #[inline(always)]
 pub fn halt() {
@@ -44,14 +71,97 @@ pub fn disable_timer_interrupts() {
     }
 }
 
+#[inline(always)]
+pub fn enable_external_interrupts() {
+    unsafe {
+        asm!(
+        "csrs sie, {mask}",
+        mask = in(reg) 1 << 9,
+        options(nomem, nostack),
+        );
+    }
+}
+
+#[inline(always)]
+pub fn enable_software_interrupts() {
+    unsafe {
+        asm!(
+        "csrsi sie, 2",
+        options(nomem, nostack),
+        );
+    }
+}
+
+#[inline(always)]
+pub fn clear_software_interrupt() {
+    unsafe {
+        asm!(
+        "csrci sip, 2",
+        options(nomem, nostack),
+        );
+    }
+}
+
+/// Enable supervisor interrupts on the current hart.
+#[inline(always)]
+pub fn enable_interrupts() {
+    unsafe {
+        asm!("csrsi sstatus, 2", options(nostack));
+    }
+}
+
+/// Disable supervisor interrupts on the current hart.
+#[inline(always)]
+pub fn disable_interrupts() {
+    unsafe {
+        asm!("csrci sstatus, 2", options(nostack));
+    }
+}
+
+/// Check if supervisor interrupts are enabled on the current hart.
+#[inline(always)]
+pub fn interrupts_enabled() -> bool {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrr {}, sstatus", out(reg) sstatus, options(nomem, nostack));
+    }
+    sstatus & 2 != 0
+}
+
+/// Run the given closure with supervisor interrupts disabled on the current hart.
+/// Locks that are also taken inside of trap handlers must only be held
+/// this way, otherwise a trap on the same hart would deadlock.
+#[inline(always)]
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrrci {}, sstatus, 2", out(reg) sstatus, options(nostack));
+    }
+
+    let result = f();
+
+    if sstatus & 2 != 0 {
+        unsafe {
+            asm!("csrsi sstatus, 2", options(nostack));
+        }
+    }
+
+    result
+}
+
+/// Enable supervisor mode traps on the current hart.
+/// This also sets up the emergency stack the trap vector
+/// switches to in case of a kernel stack overflow.
 #[inline(always)]
 pub fn enable_s_mode_traps() {
     unsafe {
         asm!(
+        "csrw sscratch, {}",
         "csrw stvec, {}",
         "csrsi sstatus, 2",
         options(nomem, nostack),
-        in(reg) s_mode_trap_handler as usize,
+        in(reg) trap_stack_top(hart_id()),
+        in(reg) _s_trap_vector as usize,
         );
     }
 }
@@ -0,0 +1,49 @@
+use crate::arch::backtrace::{frame_pointer, FrameIter};
+use crate::ksyms;
+
+/// Print a single backtrace line in the form
+/// `#3 0x80201234 risky::arch::rv64::memory::kernel_allocator::kmalloc+0x44`.
+fn print_frame(index: usize, pc: usize) {
+    // Return addresses point behind the call instruction, which might
+    // already be the start of the next function. So resolve `pc - 1`.
+    match ksyms::lookup(pc.saturating_sub(1)) {
+        Some(symbol) => println!("#{} {:#x} {}+{:#x}", index, pc, symbol.name, pc - symbol.address),
+        None => println!("#{} {:#x} <unknown>", index, pc),
+    }
+}
+
+fn print_header() {
+    if !ksyms::is_available() {
+        println!("(kernel symbol table missing, run tools/ksyms.py on the image)");
+    }
+}
+
+/// Print a backtrace of the calling code.
+#[inline(never)]
+pub fn print() {
+    println!("Backtrace:");
+    print_header();
+
+    for (index, frame) in FrameIter::new(frame_pointer()).enumerate() {
+        print_frame(index, frame.pc);
+    }
+}
+
+/// Print a backtrace of code that was interrupted by a trap.
+/// `pc` is the trapping instruction (`sepc`) and `fp` the
+/// frame pointer at the time of the trap.
+pub fn print_from(pc: usize, fp: usize) {
+    println!("Backtrace:");
+    print_header();
+
+    // The trapping instruction is not part of any frame record,
+    // so it is printed before walking the frames of its callers.
+    match ksyms::lookup(pc) {
+        Some(symbol) => println!("#0 {:#x} {}+{:#x}", pc, symbol.name, symbol.offset),
+        None => println!("#0 {:#x} <unknown>", pc),
+    }
+
+    for (index, frame) in FrameIter::new(fp).enumerate() {
+        print_frame(index + 1, frame.pc);
+    }
+}
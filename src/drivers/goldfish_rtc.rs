@@ -0,0 +1,149 @@
+// Documentation can be found here:
+// https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT
+
+use alloc::boxed::Box;
+use spin::{Mutex, Once};
+use crate::arch::paging_sv39::id_map_mmio;
+use crate::arch::plic;
+use crate::arch::trap::without_interrupts;
+use crate::dtb;
+use crate::time::Duration;
+
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+const ALARM_LOW: usize = 0x08;
+const ALARM_HIGH: usize = 0x0c;
+const IRQ_ENABLED: usize = 0x10;
+const CLEAR_ALARM: usize = 0x14;
+const CLEAR_INTERRUPT: usize = 0x1c;
+
+/// Priority of the alarm interrupt at the PLIC.
+const ALARM_PRIORITY: u32 = 1;
+
+pub type AlarmCallback = Box<dyn FnOnce() + Send>;
+
+static RTC: Once<GoldfishRtc> = Once::new();
+static ALARM_CALLBACK: Mutex<Option<AlarmCallback>> = Mutex::new(None);
+
+/// The Goldfish real time clock of QEMU virt.
+/// It counts nanoseconds since the UNIX epoch.
+#[derive(Debug)]
+pub struct GoldfishRtc {
+    base: usize,
+    size: usize,
+    irq: Option<u32>,
+}
+
+impl GoldfishRtc {
+    fn from_device_tree(tree: &dtb::DeviceTree) -> Option<Self> {
+        let node = tree.find_compatible("google,goldfish-rtc")?;
+        let (base, size) = node.reg().next()?;
+        let irq = node.interrupts().next();
+
+        Some(Self { base, size, irq })
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Returns the current time in nanoseconds since the UNIX epoch.
+    pub fn now_nanos(&self) -> u64 {
+        // Reading the low half latches the high half.
+        let low = self.read(TIME_LOW) as u64;
+        let high = self.read(TIME_HIGH) as u64;
+        (high << 32) | low
+    }
+
+    /// Returns the current time since the UNIX epoch.
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos(self.now_nanos())
+    }
+
+    /// Raise the alarm interrupt once the clock reaches the given time.
+    /// Replaces a previously set alarm.
+    fn arm(&self, nanos: u64) {
+        self.write(IRQ_ENABLED, 1);
+        // Writing the low half arms the alarm, so it has to be written last.
+        self.write(ALARM_HIGH, (nanos >> 32) as u32);
+        self.write(ALARM_LOW, nanos as u32);
+    }
+
+    fn disarm(&self) {
+        self.write(CLEAR_ALARM, 1);
+        self.write(IRQ_ENABLED, 0);
+    }
+}
+
+/// Find the RTC in the device tree and route its alarm interrupt to the current hart.
+/// Returns false if the system has no Goldfish RTC.
+/// Requires the PLIC to be initialized.
+pub fn init() -> bool {
+    let Some(rtc) = dtb::get().and_then(GoldfishRtc::from_device_tree) else {
+        return false;
+    };
+
+    id_map_mmio(rtc.base, rtc.size);
+    rtc.disarm();
+
+    let rtc = RTC.call_once(|| rtc);
+    if let Some(irq) = rtc.irq {
+        plic::register_handler(irq, ALARM_PRIORITY, handle_alarm);
+    }
+
+    println!("| Goldfish RTC at {:#x}, alarm irq {:?}", rtc.base, rtc.irq);
+    true
+}
+
+/// Returns the RTC, None if the system has none or [`init`] has not been called yet.
+pub fn get() -> Option<&'static GoldfishRtc> {
+    RTC.get()
+}
+
+/// Call a function once the clock reaches the given time since the UNIX epoch.
+/// The callback runs in trap context with interrupts disabled and must not block.
+/// Replaces a previously set alarm. Returns false if there is no RTC with an alarm interrupt.
+pub fn set_alarm(at: Duration, callback: impl FnOnce() + Send + 'static) -> bool {
+    let Some(rtc) = get().filter(|rtc| rtc.irq.is_some()) else {
+        return false;
+    };
+
+    let nanos = at.as_nanos().min(u64::MAX as u128) as u64;
+    without_interrupts(|| {
+        *ALARM_CALLBACK.lock() = Some(Box::new(callback));
+        rtc.arm(nanos);
+    });
+
+    true
+}
+
+/// Cancel the pending alarm. Returns false if no alarm was set.
+pub fn cancel_alarm() -> bool {
+    let Some(rtc) = get() else {
+        return false;
+    };
+
+    without_interrupts(|| {
+        rtc.disarm();
+        ALARM_CALLBACK.lock().take().is_some()
+    })
+}
+
+fn handle_alarm(_irq: u32) {
+    let Some(rtc) = get() else {
+        return;
+    };
+
+    rtc.write(CLEAR_INTERRUPT, 1);
+    rtc.disarm();
+
+    // Take the callback out first, so that it can set a new alarm.
+    let callback = ALARM_CALLBACK.lock().take();
+    if let Some(callback) = callback {
+        callback();
+    }
+}
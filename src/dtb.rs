@@ -0,0 +1,416 @@
+// The format is described in the devicetree specification:
+// https://github.com/devicetree-org/devicetree-specification/blob/main/source/chapter5-flattened-format.rst
+
+use spin::Once;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// The device tree passed to the kernel by the firmware.
+static DEVICE_TREE: Once<DeviceTree> = Once::new();
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DtbError {
+    NullPointer,
+    InvalidMagic(u32),
+    UnsupportedVersion(u32),
+    AlreadyInitialized,
+}
+
+/// Parse the device tree blob at the given address
+/// and make it available through [`get`].
+/// # Safety
+/// The address must point to a valid device tree blob
+/// which stays mapped and unmodified for the lifetime of the kernel.
+pub unsafe fn init(addr: usize) -> Result<&'static DeviceTree, DtbError> {
+    if DEVICE_TREE.is_completed() {
+        return Err(DtbError::AlreadyInitialized);
+    }
+
+    let tree = DeviceTree::from_addr(addr)?;
+    Ok(DEVICE_TREE.call_once(|| tree))
+}
+
+/// Returns the device tree of the system.
+/// Returns None if [`init`] has not been called successfully.
+pub fn get() -> Option<&'static DeviceTree> {
+    DEVICE_TREE.get()
+}
+
+/// Returns the value of a `key=value` option in `/chosen/bootargs`.
+/// Options without a value, like `key`, return an empty string.
+pub fn boot_argument(key: &str) -> Option<&'static str> {
+    let bootargs = get()?.find_node("/chosen")?.property("bootargs")?.as_str()?;
+
+    bootargs
+        .split_whitespace()
+        .map(|argument| argument.split_once('=').unwrap_or((argument, "")))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+/// A flattened device tree blob.
+#[derive(Debug)]
+pub struct DeviceTree {
+    addr: usize,
+    total_size: usize,
+    struct_offset: usize,
+    struct_size: usize,
+    strings_offset: usize,
+}
+
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(&'a str, &'a [u8]),
+    Nop,
+    End,
+}
+
+impl DeviceTree {
+    /// Read the header of the device tree blob at the given address.
+    /// # Safety
+    /// The address must point to a valid device tree blob.
+    pub unsafe fn from_addr(addr: usize) -> Result<Self, DtbError> {
+        if addr == 0 {
+            return Err(DtbError::NullPointer);
+        }
+
+        let header = |index: usize| u32::from_be((addr as *const u32).add(index).read_volatile());
+
+        let magic = header(0);
+        if magic != FDT_MAGIC {
+            return Err(DtbError::InvalidMagic(magic));
+        }
+
+        // Version 17 is the current one, 16 is the oldest compatible one.
+        let last_compatible_version = header(6);
+        if last_compatible_version > 17 {
+            return Err(DtbError::UnsupportedVersion(last_compatible_version));
+        }
+
+        Ok(Self {
+            addr,
+            total_size: header(1) as usize,
+            struct_offset: header(2) as usize,
+            strings_offset: header(3) as usize,
+            struct_size: header(9) as usize,
+        })
+    }
+
+    /// Returns the address of the device tree blob.
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Returns the size of the device tree blob in bytes.
+    pub fn size(&self) -> usize {
+        self.total_size
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> &[u8] {
+        assert!(offset + len <= self.total_size, "device tree access out of bounds");
+        unsafe { core::slice::from_raw_parts((self.addr + offset) as *const u8, len) }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let bytes = self.bytes(offset, 4);
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn c_str(&self, offset: usize) -> &str {
+        let tail = self.bytes(offset, self.total_size - offset);
+        let len = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+        core::str::from_utf8(&tail[..len]).unwrap_or("")
+    }
+
+    /// Decode the token at the given offset into the structure block.
+    /// Returns the token and the offset of the next one.
+    fn token(&self, offset: usize) -> (Token<'_>, usize) {
+        if offset + 4 > self.struct_size {
+            return (Token::End, offset);
+        }
+
+        let base = self.struct_offset + offset;
+        match self.read_u32(base) {
+            FDT_BEGIN_NODE => {
+                let name = self.c_str(base + 4);
+                (Token::BeginNode(name), offset + 4 + align4(name.len() + 1))
+            },
+            FDT_END_NODE => (Token::EndNode, offset + 4),
+            FDT_PROP => {
+                let len = self.read_u32(base + 4) as usize;
+                let name_offset = self.read_u32(base + 8) as usize;
+                let name = self.c_str(self.strings_offset + name_offset);
+                let value = self.bytes(base + 12, len);
+                (Token::Prop(name, value), offset + 12 + align4(len))
+            },
+            FDT_NOP => (Token::Nop, offset + 4),
+            // Unknown tokens can not be skipped, so they end the walk as well.
+            FDT_END | _ => (Token::End, offset),
+        }
+    }
+
+    /// Returns the root node of the tree.
+    pub fn root(&self) -> Node<'_> {
+        self.nodes().next().expect("device tree has no root node")
+    }
+
+    /// Iterate over all nodes of the tree in depth-first order.
+    pub fn nodes(&self) -> NodeIter<'_> {
+        NodeIter { tree: self, offset: 0, depth: 0 }
+    }
+
+    /// Find a node by its full path, for example `/soc/plic@c000000`.
+    /// Node names without a unit address match any unit address,
+    /// so `/cpus/cpu` finds the first cpu node.
+    pub fn find_node(&self, path: &str) -> Option<Node<'_>> {
+        let mut node = self.root();
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| {
+                child.name() == component || child.name().split('@').next() == Some(component)
+            })?;
+        }
+
+        Some(node)
+    }
+
+    /// Find the first node compatible with the given string.
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node<'_>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    /// Iterate over all nodes compatible with the given string.
+    pub fn find_all_compatible<'a>(&'a self, compatible: &'a str) -> impl Iterator<Item = Node<'a>> + 'a {
+        self.nodes().filter(move |node| node.is_compatible(compatible))
+    }
+
+    /// Find the node with the given phandle.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'_>> {
+        self.nodes().find(|node| node.phandle() == Some(phandle))
+    }
+}
+
+fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+/// Iterator over nodes of the tree in depth-first order.
+pub struct NodeIter<'a> {
+    tree: &'a DeviceTree,
+    offset: usize,
+    depth: usize,
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, next) = self.tree.token(self.offset);
+            let offset = self.offset;
+            self.offset = next;
+
+            match token {
+                Token::BeginNode(name) => {
+                    let node = Node { tree: self.tree, offset, body: next, name, depth: self.depth };
+                    self.depth += 1;
+                    return Some(node);
+                },
+                Token::EndNode => self.depth = self.depth.saturating_sub(1),
+                Token::Prop(..) | Token::Nop => {},
+                Token::End => return None,
+            }
+        }
+    }
+}
+
+/// A node of the device tree.
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    tree: &'a DeviceTree,
+    /// Offset of the `BEGIN_NODE` token.
+    offset: usize,
+    /// Offset of the first token after the node name.
+    body: usize,
+    name: &'a str,
+    depth: usize,
+}
+
+impl<'a> Node<'a> {
+    /// Returns the name of the node including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns the unit address of the node, the part of the name after `@`.
+    pub fn unit_address(&self) -> Option<usize> {
+        let (_, address) = self.name.split_once('@')?;
+        usize::from_str_radix(address, 16).ok()
+    }
+
+    /// Returns the depth of the node, the root node has a depth of 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Iterate over the properties of the node.
+    pub fn properties(&self) -> impl Iterator<Item = Property<'a>> + 'a {
+        let tree = self.tree;
+        let mut offset = self.body;
+
+        core::iter::from_fn(move || loop {
+            let (token, next) = tree.token(offset);
+            offset = next;
+
+            match token {
+                Token::Prop(name, value) => return Some(Property { name, value }),
+                Token::Nop => continue,
+                // Properties always come before child nodes.
+                _ => return None,
+            }
+        })
+    }
+
+    /// Find a property of the node by name.
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    /// Iterate over the direct children of the node.
+    pub fn children(&self) -> impl Iterator<Item = Node<'a>> + 'a {
+        let depth = self.depth + 1;
+        let mut nodes = NodeIter { tree: self.tree, offset: self.body, depth };
+
+        core::iter::from_fn(move || loop {
+            let node = nodes.next()?;
+            if node.depth < depth {
+                // We left this node and reached one of its siblings.
+                return None;
+            } else if node.depth == depth {
+                return Some(node);
+            }
+        })
+        .fuse()
+    }
+
+    /// Returns the parent of the node or None for the root node.
+    pub fn parent(&self) -> Option<Node<'a>> {
+        if self.depth == 0 {
+            return None;
+        }
+
+        // The parent is the last node before this one with a smaller depth.
+        self.tree
+            .nodes()
+            .take_while(|node| node.offset < self.offset)
+            .filter(|node| node.depth == self.depth - 1)
+            .last()
+    }
+
+    /// Check if the `compatible` property of the node contains the given string.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible")
+            .map(|property| property.strings().any(|s| s == compatible))
+            .unwrap_or(false)
+    }
+
+    /// Returns the phandle of the node if it has one.
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle").and_then(|property| property.as_u32())
+    }
+
+    /// Returns the `#address-cells` of the node, used by its children.
+    pub fn address_cells(&self) -> usize {
+        self.property("#address-cells").and_then(|p| p.as_u32()).unwrap_or(2) as usize
+    }
+
+    /// Returns the `#size-cells` of the node, used by its children.
+    pub fn size_cells(&self) -> usize {
+        self.property("#size-cells").and_then(|p| p.as_u32()).unwrap_or(1) as usize
+    }
+
+    /// Iterate over the `(address, size)` pairs of the `reg` property.
+    pub fn reg(&self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (address_cells, size_cells) = self
+            .parent()
+            .map(|parent| (parent.address_cells(), parent.size_cells()))
+            .unwrap_or((2, 1));
+        let value = self.property("reg").map(|property| property.value).unwrap_or(&[]);
+        let stride = (address_cells + size_cells) * 4;
+
+        value.chunks_exact(stride.max(4)).map(move |chunk| {
+            let address = read_cells(&chunk[..address_cells * 4]);
+            let size = read_cells(&chunk[address_cells * 4..]);
+            (address as usize, size as usize)
+        })
+    }
+
+    /// Iterate over the cells of the `interrupts` property.
+    pub fn interrupts(&self) -> impl Iterator<Item = u32> + 'a {
+        self.property("interrupts")
+            .into_iter()
+            .flat_map(|property| property.u32s())
+    }
+}
+
+impl core::fmt::Debug for Node<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Node")
+            .field("name", &self.name)
+            .field("depth", &self.depth)
+            .finish()
+    }
+}
+
+/// Read a big endian number made up of one or two 32 bit cells.
+fn read_cells(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+/// A property of a device tree node.
+#[derive(Debug, Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// Interpret the value as a single 32 bit cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        self.u32s().next()
+    }
+
+    /// Interpret the value as a 64 bit number made up of one or two cells.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 | 8 => Some(read_cells(self.value)),
+            _ => None,
+        }
+    }
+
+    /// Interpret the value as a single string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.strings().next()
+    }
+
+    /// Iterate over the NUL separated strings of the value.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+
+    /// Iterate over the value as a list of 32 bit cells.
+    pub fn u32s(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+    }
+}
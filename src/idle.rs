@@ -0,0 +1,130 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use opensbi::{hart_suspend, suspend_type, SbiError};
+use crate::arch::consts::MAX_HARTS;
+use crate::arch::hart_id;
+use crate::arch::trap::{disable_interrupts, enable_interrupts, halt};
+use crate::smp::PerHart;
+use crate::time::{timer, ticks_to_duration, uptime, Duration, Instant};
+
+/// Cleared once the SBI implementation rejected a retentive suspend.
+static SUSPEND_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
+/// How much time a hart spent idle.
+struct IdleCounters {
+    /// Timebase ticks spent idle.
+    idle_ticks: AtomicU64,
+    /// Number of times the hart went idle.
+    entries: AtomicU64,
+    /// Number of times the hart was suspended through SBI HSM.
+    suspends: AtomicU64,
+}
+
+static IDLE_COUNTERS: PerHart<IdleCounters> = PerHart::new([const {
+    IdleCounters {
+        idle_ticks: AtomicU64::new(0),
+        entries: AtomicU64::new(0),
+        suspends: AtomicU64::new(0),
+    }
+}; MAX_HARTS]);
+
+/// Idle statistics of a hart.
+#[derive(Debug, Clone, Copy)]
+pub struct IdleStats {
+    /// Total time spent idle.
+    pub idle_time: Duration,
+    /// Number of times the hart went idle.
+    pub entries: u64,
+    /// Number of times the hart was suspended through SBI HSM instead of using `wfi`.
+    pub suspends: u64,
+}
+
+impl IdleStats {
+    /// Returns the share of the uptime the hart spent idle, in tenths of a percent.
+    pub fn residency_permille(&self) -> u64 {
+        let uptime = uptime().as_nanos();
+        if uptime == 0 {
+            return 0;
+        }
+
+        (self.idle_time.as_nanos() * 1000 / uptime) as u64
+    }
+}
+
+/// Returns the idle statistics of the given hart.
+pub fn stats(hart_id: usize) -> IdleStats {
+    let counters = IDLE_COUNTERS.get_for(hart_id);
+
+    IdleStats {
+        idle_time: ticks_to_duration(counters.idle_ticks.load(Ordering::Relaxed)),
+        entries: counters.entries.load(Ordering::Relaxed),
+        suspends: counters.suspends.load(Ordering::Relaxed),
+    }
+}
+
+/// Print the idle statistics of all online harts.
+pub fn print_stats() {
+    println!("+ Idle statistics:");
+    for hart in crate::smp::online_harts().iter() {
+        let stats = stats(hart);
+        let residency = stats.residency_permille();
+        println!(
+            "| Hart {}: idle {:?} ({}.{}%), {} entries, {} suspends",
+            hart, stats.idle_time, residency / 10, residency % 10, stats.entries, stats.suspends
+        );
+    }
+}
+
+/// Put the current hart to sleep until the next interrupt.
+///
+/// The periodic tick is stopped while the hart sleeps, so that only real
+/// deadlines wake it up. The hart is suspended through SBI HSM if the
+/// SBI implementation supports it, otherwise it waits with `wfi`.
+/// Pending interrupts are handled before this function returns.
+pub fn idle() {
+    // Interrupts stay disabled until the hart went to sleep, so a wakeup
+    // between checking for work and sleeping is not lost. A pending interrupt
+    // still ends the suspend or `wfi`, it is only handled afterwards.
+    disable_interrupts();
+
+    let counters = IDLE_COUNTERS.get();
+    let tick_was_running = timer::tick_running();
+    if tick_was_running {
+        timer::stop_tick();
+    }
+
+    let start = Instant::now();
+    if suspend() {
+        counters.suspends.fetch_add(1, Ordering::Relaxed);
+    } else {
+        halt();
+    }
+    let end = Instant::now();
+
+    counters.idle_ticks.fetch_add(end.ticks().saturating_sub(start.ticks()), Ordering::Relaxed);
+    counters.entries.fetch_add(1, Ordering::Relaxed);
+
+    if tick_was_running {
+        timer::start_tick();
+    }
+
+    enable_interrupts();
+}
+
+/// Try a retentive suspend of the current hart.
+/// Returns false if the SBI implementation does not support it.
+fn suspend() -> bool {
+    if !SUSPEND_SUPPORTED.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    // A retentive suspend returns like `wfi` once an interrupt is pending,
+    // so the resume address and the opaque value are unused.
+    let result = hart_suspend(suspend_type::RETENTIVE, 0, 0);
+    if result.error == SbiError::Success {
+        return true;
+    }
+
+    println!("Hart {} can not be suspended ({:?}), falling back to wfi", hart_id(), result.error);
+    SUSPEND_SUPPORTED.store(false, Ordering::Relaxed);
+    false
+}
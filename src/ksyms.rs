@@ -0,0 +1,129 @@
+use core::mem::size_of;
+
+/// Number of bytes reserved for the kernel symbol table.
+const KSYMS_CAPACITY: usize = 0x0004_0000;
+/// Magic value ("KSYM") at the start of a filled in symbol table.
+const KSYMS_MAGIC: u32 = 0x4D59_534B;
+
+#[repr(C, align(8))]
+pub struct KsymsArea([u8; KSYMS_CAPACITY]);
+
+/// Space for the kernel symbol table.
+/// The linked image only contains zeroes here, the actual table
+/// is written into the image by `tools/ksyms.py` after linking.
+/// The layout must be kept in sync with that script.
+#[no_mangle]
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: KsymsArea = KsymsArea([0; KSYMS_CAPACITY]);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    magic: u32,
+    count: u32,
+    strtab_offset: u32,
+    strtab_len: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    address: u64,
+    size: u32,
+    name_offset: u32,
+    name_len: u32,
+    _reserved: u32,
+}
+
+/// A symbol that an address was resolved to.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub address: usize,
+    /// The offset of the looked up address from the start of the symbol.
+    pub offset: usize,
+}
+
+/// Returns the base of the symbol table.
+///
+/// The compiler only sees the zeroes of the linked image, so the
+/// pointer is passed through [`core::hint::black_box`] to keep it
+/// from constant folding reads of the table.
+#[inline(always)]
+fn base() -> *const u8 {
+    core::hint::black_box(KSYMS.0.as_ptr())
+}
+
+fn header() -> Option<Header> {
+    let header = unsafe { (base() as *const Header).read() };
+
+    if header.magic != KSYMS_MAGIC {
+        return None;
+    }
+
+    let entries_end = size_of::<Header>() + header.count as usize * size_of::<Entry>();
+    let strtab_end = header.strtab_offset as usize + header.strtab_len as usize;
+    if entries_end > KSYMS_CAPACITY || strtab_end > KSYMS_CAPACITY {
+        return None;
+    }
+
+    Some(header)
+}
+
+fn entries(header: &Header) -> &'static [Entry] {
+    unsafe {
+        core::slice::from_raw_parts(
+            base().add(size_of::<Header>()) as *const Entry,
+            header.count as usize,
+        )
+    }
+}
+
+fn name(header: &Header, entry: &Entry) -> &'static str {
+    if entry.name_offset.saturating_add(entry.name_len) > header.strtab_len {
+        return "<invalid>";
+    }
+
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            base().add(header.strtab_offset as usize + entry.name_offset as usize),
+            entry.name_len as usize,
+        )
+    };
+
+    core::str::from_utf8(bytes).unwrap_or("<invalid>")
+}
+
+/// Check if the symbol table has been embedded into the image.
+pub fn is_available() -> bool {
+    header().is_some()
+}
+
+/// Resolve an address to the function containing it.
+/// Returns None if the symbol table is missing or
+/// the address does not belong to any known function.
+pub fn lookup(address: usize) -> Option<Symbol> {
+    let header = header()?;
+    let entries = entries(&header);
+
+    // Index of the last symbol starting at or before the address.
+    let index = entries.partition_point(|entry| entry.address as usize <= address).checked_sub(1)?;
+    let entry = &entries[index];
+    let offset = address - entry.address as usize;
+
+    // Symbols without a size (assembly labels) cover everything up to the next symbol.
+    let end = match entry.size {
+        0 => entries.get(index + 1).map(|next| next.address as usize).unwrap_or(usize::MAX),
+        size => entry.address as usize + size as usize,
+    };
+    if address >= end {
+        return None;
+    }
+
+    Some(Symbol {
+        name: name(&header, entry),
+        address: entry.address as usize,
+        offset,
+    })
+}
@@ -9,7 +9,12 @@
 
 extern crate alloc;
 
-use crate::arch::trap::halt;
+use core::cell::Cell;
+use crate::arch::hart_id;
+use crate::arch::memory::slab::{self, KmemCache};
+use crate::task::{sync, Affinity};
+use crate::thread::realtime::{self, RtParams};
+use crate::time::{Duration, Instant};
 
 #[macro_use]
 mod arch;
@@ -17,29 +22,44 @@ mod panic;
 mod logger;
 mod allocator;
 mod task;
+mod ksyms;
+mod backtrace;
+mod dtb;
+mod smp;
+mod time;
+mod idle;
+mod drivers;
+mod thread;
 
 #[no_mangle]
 pub extern "C" fn kmain() -> ! {
     println!("Kernel started");
-    /*
-    println!("Running async test...");
-    let mut executor = SimpleExecutor::new();
-    executor.spawn(Task::new(test_async()));
-    executor.run();
-    */
 
-    loop {
-        halt();
+    println!("Running async test...");
+    task::spawn_local(test_async()).detach();
+    task::spawn(test_sync()).detach();
+    for n in 0..8 {
+        task::spawn(async move {
+            println!("Task {} running on hart {}", n, hart_id());
+        }).detach();
     }
+    task::Builder::new().name("housekeeping").spawn(housekeeping()).detach();
+    test_threads();
+    test_slab();
+    for hart in 0..4 {
+        task::spawn_with_affinity(async move {
+            println!("Pinned task running on hart {} (expected {})", hart_id(), hart);
+        }, Affinity::Pinned(hart)).detach();
+    }
+
+    task::smp_executor::run();
 }
 
 #[no_mangle]
 pub extern "C" fn kmain_ap() -> ! {
     println!("Kernel started (AP)");
 
-    loop {
-        halt();
-    }
+    task::smp_executor::run();
 }
 
 async fn async_number() -> u32 {
@@ -54,4 +74,163 @@ async fn test_async() {
     let result = async_number().await;
     let result = async_add(result, 10).await;
     println!("Async number: {}", result);
+
+    let start = Instant::now();
+    time::sleep(Duration::from_millis(100)).await;
+    println!("Slept for {:?}", start.elapsed());
+
+    let result = time::timeout(Duration::from_millis(10), time::sleep(Duration::from_secs(1))).await;
+    println!("Timeout result: {:?}", result);
+
+    let sum = task::spawn(async_add(20, 22));
+    println!("Joined task result: {:?}", sum.await);
+
+    let sleeper = task::spawn(time::sleep(Duration::from_secs(10)));
+    sleeper.abort();
+    println!("Aborted task result: {:?}", sleeper.await);
+
+    STEPS.with(|steps| steps.set(steps.get() + 1));
+    println!("Task-local steps: {}", STEPS.with(|steps| steps.get()));
+}
+
+/// Exercise the async synchronization primitives across harts.
+async fn test_sync() {
+    static COUNTER: sync::Mutex<u32> = sync::Mutex::new(0);
+    static CONFIG: sync::RwLock<u32> = sync::RwLock::new(1);
+    static LIMIT: sync::Semaphore = sync::Semaphore::new(2);
+    static READY: sync::Event = sync::Event::new();
+    static DONE: sync::Notify = sync::Notify::new();
+
+    let (sender, mut receiver) = sync::mpsc::channel(4);
+    for n in 0..4 {
+        let sender = sender.clone();
+        task::spawn(async move {
+            READY.wait().await;
+            let _permit = LIMIT.acquire().await.unwrap();
+            let step = *CONFIG.read().await;
+            let mut counter = COUNTER.lock().await;
+            time::sleep(Duration::from_millis(1)).await;
+            *counter += step;
+            let _ = sender.send(n).await;
+            DONE.notify_one();
+        }).detach();
+    }
+    drop(sender);
+
+    *CONFIG.write().await = 2;
+    READY.set();
+
+    let mut received = 0;
+    while receiver.recv().await.is_some() {
+        received += 1;
+    }
+    DONE.notified().await;
+
+    let (reply, response) = sync::oneshot::channel();
+    task::spawn(async move {
+        let _ = reply.send(*COUNTER.lock().await);
+    }).detach();
+    println!("Sync test: {} messages, counter {:?}", received, response.await);
+}
+
+/// Exercise preemptive kernel threads.
+fn test_threads() {
+    let workers: alloc::vec::Vec<_> = (0..3u64)
+        .map(|n| {
+            thread::Builder::new().name(alloc::format!("worker/{}", n)).nice(n as i8 * 5).spawn(move || {
+                // Busy loop, so that the worker has to be preempted.
+                let mut sum = 0u64;
+                for i in 0..2_000_000 {
+                    sum = sum.wrapping_add(i * n);
+                }
+                sum
+            })
+        })
+        .collect();
+
+    thread::spawn_thread(move || {
+        let start = Instant::now();
+        thread::sleep(Duration::from_millis(50));
+        println!("Thread slept for {:?}", start.elapsed());
+
+        for worker in workers {
+            let thread = worker.thread().clone();
+            let sum = worker.join();
+            println!("Thread {} ({}) finished: {}", thread.id(), thread.name().unwrap_or("?"), sum);
+        }
+        thread::yield_now();
+        println!("Thread {} done on hart {}", thread::current().id(), hart_id());
+    });
+
+    let mut cycles = 0;
+    let control = RtParams::new(Duration::from_micros(500), Duration::from_millis(5))
+        .with_deadline(Duration::from_millis(2));
+    match realtime::spawn_periodic("control", control, move || {
+        cycles += 1;
+        cycles < 200
+    }) {
+        Ok(handle) => println!("Control loop admitted on hart {:?}", handle.thread().realtime().map(|task| task.hart())),
+        Err(error) => println!("Control loop rejected: {}", error),
+    }
+
+    let overload = RtParams::new(Duration::from_millis(5), Duration::from_millis(5));
+    if let Err(error) = realtime::spawn_periodic("overload", overload, || false) {
+        println!("Overloaded real-time thread rejected: {}", error);
+    }
+}
+
+struct Particle {
+    position: [i32; 3],
+    generation: u32,
+}
+
+static PARTICLES: KmemCache<Particle> = KmemCache::with_constructor("particle", |particle| {
+    particle.write(Particle { position: [0; 3], generation: 0 });
+});
+
+/// Exercise the slab allocator with a cache of constructed objects.
+fn test_slab() {
+    let particles: alloc::vec::Vec<_> = (0..100)
+        .map(|n| {
+            let particle = PARTICLES.alloc().expect("out of memory");
+            unsafe {
+                (*particle).position[0] += n;
+                (*particle).generation += 1;
+            }
+            particle
+        })
+        .collect();
+
+    for particle in particles {
+        PARTICLES.free(particle);
+    }
+
+    // Freed objects keep their state, so the generation counts up on reuse.
+    let particle = PARTICLES.alloc().expect("out of memory");
+    println!("Particle reused: generation {}, x {}", unsafe { (*particle).generation }, unsafe { (*particle).position[0] });
+    PARTICLES.free(particle);
+
+    let stats = PARTICLES.stats();
+    println!("Particle cache: {} allocs, {} frees, {} in use", stats.allocations, stats.frees, stats.objects_in_use);
+}
+
+task_local! {
+    static STEPS: Cell<u32> = Cell::new(0);
+}
+
+/// Periodic kernel housekeeping.
+async fn housekeeping() {
+    let mut interval = time::interval(Duration::from_secs(60));
+    // The first tick completes immediately.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        idle::print_stats();
+        arch::memory::print_stats();
+        slab::print_caches();
+        task::print_tasks();
+        thread::print_threads();
+        realtime::print_realtime();
+    }
 }
\ No newline at end of file
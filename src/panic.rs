@@ -1,8 +1,140 @@
+use core::fmt::Write;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use opensbi::{reset_reason, reset_type, system_reset};
+use crate::arch::hart_id;
+use crate::arch::logger::OpenSbiLogger;
+use crate::arch::macros::print::{take_over_console, PRINT_LOCK};
+use crate::arch::trap::{disable_interrupts, halt, InterruptedContext};
+use crate::backtrace;
+use crate::smp;
+use crate::task;
+
+/// Hart id of the hart that panicked first, `usize::MAX` if none did.
+static PANIC_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// Set once the other harts have been asked to park.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Number of harts that reported their state and parked.
+static PARKED_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Shut the system down after a panic instead of only halting all harts.
+const SHUTDOWN_ON_PANIC: bool = true;
+
+/// Check if a hart panicked and the other harts have been asked to park.
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Acquire)
+}
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    disable_interrupts();
+    let hart = hart_id();
+
+    if let Err(first) = PANIC_HART.compare_exchange(usize::MAX, hart, Ordering::AcqRel, Ordering::Acquire) {
+        if first == hart {
+            // Panicked while panicking, print as little as possible.
+            take_over_console();
+            println!("Nested panic on hart {}: {}", hart, info);
+            halt_forever();
+        }
+
+        // Another hart is already taking the system down.
+        println!("Hart {} panicked as well: {}", hart, info);
+        park(None);
+    }
+
+    take_over_console();
+    println!("Kernel panic on hart {}: {}", hart, info);
+    backtrace::print();
+
+    stop_other_harts();
+    // A hart that did not respond may still hold the print lock.
+    take_over_console();
+
+    if SHUTDOWN_ON_PANIC {
+        println!("Shutting down...");
+        let result = system_reset(reset_type::SHUTDOWN, reset_reason::SYSTEM_FAILURE);
+        println!("Shutdown failed: {:?}", result.error);
+    }
+
+    halt_forever();
+}
+
+/// Ask all other online harts to park and wait until they did.
+/// Harts running with interrupts disabled can not respond,
+/// so they are only waited for a limited amount of time.
+fn stop_other_harts() {
+    let others = smp::online_harts().without(hart_id());
+    if others.is_empty() {
+        return;
+    }
+
+    STOP_REQUESTED.store(true, Ordering::Release);
+    smp::kick(others);
+
+    let mut spins = 0;
+    while PARKED_HARTS.load(Ordering::Acquire) < others.count() && spins < 100_000_000 {
+        core::hint::spin_loop();
+        spins += 1;
+    }
+
+    let parked = PARKED_HARTS.load(Ordering::Acquire);
+    if parked < others.count() {
+        println!("Only {} of {} harts responded to the stop request", parked, others.count());
+    }
+}
+
+/// Report the state of the current hart and stop it for good.
+/// Called from the software interrupt handler once a hart
+/// panicked, or by harts that panic while another one already does.
+pub fn park(context: Option<&InterruptedContext>) -> ! {
+    disable_interrupts();
+    smp::mark_offline();
+
+    let hart = hart_id();
+    let task = task::current_task_on(hart);
+
+    {
+        // Hold the lock for the whole report to keep it in one piece.
+        // The interrupted code may hold the lock itself, so only wait for a while
+        // and write without the lock if it does not become free.
+        let mut guard = None;
+        for _ in 0..1_000_000 {
+            guard = PRINT_LOCK.try_lock();
+            if guard.is_some() {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+
+        let mut unlocked = OpenSbiLogger;
+        let console = match guard.as_mut() {
+            Some(guard) => &mut **guard,
+            None => &mut unlocked,
+        };
+
+        let _ = write!(console, "Hart {} parked", hart);
+        if let Some(context) = context {
+            let _ = write!(
+                console,
+                ": sepc={:#x} sp={:#x} ra={:#x}",
+                context.epc, context.sp, context.ra
+            );
+        }
+        let _ = match task {
+            Some(task) => write!(console, " task={}\r\n", task),
+            None => write!(console, " task=none\r\n"),
+        };
+    }
+
+    PARKED_HARTS.fetch_add(1, Ordering::AcqRel);
+    halt_forever();
+}
+
+fn halt_forever() -> ! {
+    disable_interrupts();
 
-    loop {}
+    loop {
+        halt();
+    }
 }
@@ -0,0 +1,126 @@
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::arch::consts::MAX_HARTS;
+use crate::arch::hart_id;
+use crate::arch::ipi::{clear_ipi, enable_ipi, send_ipi};
+
+/// A set of harts, bit `n` selects hart `n`.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct HartMask(usize);
+
+impl HartMask {
+    /// An empty mask.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// A mask containing only the given hart.
+    pub const fn single(hart_id: usize) -> Self {
+        Self(1 << hart_id)
+    }
+
+    /// A mask containing every hart the kernel supports.
+    pub const fn all() -> Self {
+        Self((1 << MAX_HARTS) - 1)
+    }
+
+    pub const fn from_bits(bits: usize) -> Self {
+        Self(bits & Self::all().0)
+    }
+
+    pub const fn bits(self) -> usize {
+        self.0
+    }
+
+    pub const fn contains(self, hart_id: usize) -> bool {
+        self.0 & (1 << hart_id) != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn count(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub const fn without(self, hart_id: usize) -> Self {
+        Self(self.0 & !(1 << hart_id))
+    }
+
+    pub const fn intersect(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Iterate over the ids of the harts in the mask.
+    pub fn iter(self) -> impl Iterator<Item = usize> {
+        (0..MAX_HARTS).filter(move |&hart_id| self.contains(hart_id))
+    }
+}
+
+impl fmt::Debug for HartMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// One instance of `T` for every hart.
+pub struct PerHart<T> {
+    values: [T; MAX_HARTS],
+}
+
+impl<T> PerHart<T> {
+    pub const fn new(values: [T; MAX_HARTS]) -> Self {
+        Self { values }
+    }
+
+    /// Returns the instance of the current hart.
+    pub fn get(&self) -> &T {
+        &self.values[hart_id()]
+    }
+
+    /// Returns the instance of the given hart.
+    pub fn get_for(&self, hart_id: usize) -> &T {
+        &self.values[hart_id]
+    }
+
+    /// Iterate over the instances of all harts together with the hart id.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.values.iter().enumerate()
+    }
+}
+
+/// Harts that finished booting and are able to handle IPIs.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Mark the current hart as online, so that it receives IPIs.
+/// Must be called once on every hart after traps have been enabled.
+pub fn init_hart() {
+    enable_ipi();
+    ONLINE_HARTS.fetch_or(1 << hart_id(), Ordering::AcqRel);
+}
+
+/// Returns the harts that are online.
+pub fn online_harts() -> HartMask {
+    HartMask::from_bits(ONLINE_HARTS.load(Ordering::Acquire))
+}
+
+/// Mark the current hart as offline, it no longer receives IPIs.
+pub fn mark_offline() {
+    ONLINE_HARTS.fetch_and(!(1 << hart_id()), Ordering::AcqRel);
+}
+
+/// Send an IPI to the given harts, for example to wake up idle harts.
+/// The IPI ends a `wfi` or suspend and makes the hart check for work.
+pub fn kick(harts: HartMask) {
+    let harts = harts.intersect(online_harts());
+    if !harts.is_empty() {
+        send_ipi(harts.bits());
+    }
+}
+
+/// Handle a software interrupt on the current hart.
+/// Called by the trap handler.
+pub fn handle_ipi() {
+    clear_ipi();
+}
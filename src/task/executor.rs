@@ -0,0 +1,171 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::cell::RefCell;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use crate::arch::hart_id;
+use crate::arch::trap::without_interrupts;
+use crate::smp::{self, HartMask};
+use super::{Task, TaskId};
+
+/// Ids of the tasks that have been woken and need to be polled.
+/// Wakers may run in trap context or on other harts,
+/// so the queue must only be locked with interrupts disabled.
+#[derive(Default)]
+struct ReadyQueue {
+    ids: Mutex<VecDeque<TaskId>>,
+}
+
+impl ReadyQueue {
+    fn push(&self, id: TaskId) {
+        without_interrupts(|| self.ids.lock().push_back(id));
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        without_interrupts(|| self.ids.lock().pop_front())
+    }
+
+    fn is_empty(&self) -> bool {
+        without_interrupts(|| self.ids.lock().is_empty())
+    }
+}
+
+/// Tasks spawned while the executor is running.
+type SpawnQueue = Rc<RefCell<VecDeque<Task>>>;
+
+/// An executor that only polls tasks after they have been woken.
+/// It runs on the hart that created it, the SMP executor polls it
+/// and puts the hart to sleep while no task is ready.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    ready: Arc<ReadyQueue>,
+    spawned: SpawnQueue,
+    waker_cache: BTreeMap<TaskId, Waker>,
+    hart: usize,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            ready: Arc::new(ReadyQueue::default()),
+            spawned: Rc::new(RefCell::new(VecDeque::new())),
+            waker_cache: BTreeMap::new(),
+            hart: hart_id(),
+        }
+    }
+
+    /// Returns a handle that spawns tasks onto this executor.
+    /// Tasks can use it to spawn further tasks while the executor is running.
+    pub fn spawner(&self) -> Spawner {
+        Spawner { spawned: self.spawned.clone() }
+    }
+
+    fn add(&mut self, task: Task) -> TaskId {
+        let id = task.id();
+        assert!(self.tasks.insert(id, task).is_none(), "task {} spawned twice", id);
+        self.ready.push(id);
+        id
+    }
+
+    /// Move the tasks spawned through a [`Spawner`] into the executor.
+    fn add_spawned(&mut self) {
+        loop {
+            let task = self.spawned.borrow_mut().pop_front();
+            match task {
+                Some(task) => {
+                    self.add(task);
+                },
+                None => break,
+            }
+        }
+    }
+
+    /// Poll up to `limit` ready tasks. Returns the number of tasks polled.
+    /// This allows other work to run in between tasks that keep waking themselves.
+    pub fn poll_ready(&mut self, limit: usize) -> usize {
+        self.add_spawned();
+
+        let mut polled = 0;
+        while polled < limit {
+            let Some(id) = self.ready.pop() else {
+                break;
+            };
+
+            // The task may have finished already while a waker was still around.
+            let Some(task) = self.tasks.get_mut(&id) else {
+                continue;
+            };
+            polled += 1;
+
+            let waker = self
+                .waker_cache
+                .entry(id)
+                .or_insert_with(|| TaskWaker::new(id, self.ready.clone(), self.hart));
+            let mut context = Context::from_waker(waker);
+
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                self.tasks.remove(&id);
+                self.waker_cache.remove(&id);
+            }
+
+            self.add_spawned();
+        }
+
+        polled
+    }
+
+    /// Check if a task has been woken or spawned and needs to be polled.
+    pub fn has_ready_tasks(&self) -> bool {
+        !self.ready.is_empty() || !self.spawned.borrow().is_empty()
+    }
+}
+
+/// Spawns tasks onto the [`Executor`] it was created from.
+#[derive(Clone)]
+pub struct Spawner {
+    spawned: SpawnQueue,
+}
+
+impl Spawner {
+    pub(super) fn spawn_task(&self, task: Task) -> TaskId {
+        let id = task.id();
+        self.spawned.borrow_mut().push_back(task);
+        id
+    }
+}
+
+/// Wakes a task by pushing its id into the ready queue of its executor.
+struct TaskWaker {
+    id: TaskId,
+    ready: Arc<ReadyQueue>,
+    /// The hart the executor runs on.
+    hart: usize,
+}
+
+impl TaskWaker {
+    fn new(id: TaskId, ready: Arc<ReadyQueue>, hart: usize) -> Waker {
+        Waker::from(Arc::new(Self { id, ready, hart }))
+    }
+
+    fn wake_task(&self) {
+        self.ready.push(self.id);
+
+        // The executor may be sleeping on another hart.
+        if self.hart != hart_id() {
+            smp::kick(HartMask::single(self.hart));
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
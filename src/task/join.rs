@@ -0,0 +1,221 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use crate::arch::trap::without_interrupts;
+use super::registry::{self, TaskInfo};
+use super::{with_current_task, TaskId};
+
+/// Error returned by a [`JoinHandle`] if the task did not run to completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted, or its executor dropped it.
+    Cancelled,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+        }
+    }
+}
+
+struct JoinInner<T> {
+    /// The result until the join handle takes it.
+    result: Option<Result<T, JoinError>>,
+    finished: bool,
+    /// Waker of the task awaiting the join handle.
+    join_waker: Option<Waker>,
+    /// Waker of the task itself, used to cancel it.
+    task_waker: Option<Waker>,
+}
+
+/// State shared between a task and its [`JoinHandle`].
+/// It may be accessed from timer callbacks, so the lock
+/// is only taken with interrupts disabled.
+struct JoinState<T> {
+    inner: Mutex<JoinInner<T>>,
+    cancel_requested: AtomicBool,
+}
+
+impl<T> JoinState<T> {
+    fn finish(&self, result: Result<T, JoinError>) {
+        let waker = without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            if inner.finished {
+                return None;
+            }
+
+            inner.finished = true;
+            inner.result = Some(result);
+            inner.task_waker = None;
+            inner.join_waker.take()
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Wraps the future of a task. It stores the output for the [`JoinHandle`],
+/// drops the future once the task is cancelled and keeps the task registered
+/// for debugging while it has not finished.
+pub(super) struct Joinable<F: Future> {
+    future: Option<F>,
+    state: Arc<JoinState<F::Output>>,
+    info: Arc<TaskInfo>,
+}
+
+/// Create the wrapped future of a new task together with its join handle.
+pub(super) fn joinable<F: Future>(future: F, info: TaskInfo) -> (Joinable<F>, JoinHandle<F::Output>) {
+    let info = Arc::new(info);
+    registry::register(info.clone());
+
+    let state = Arc::new(JoinState {
+        inner: Mutex::new(JoinInner {
+            result: None,
+            finished: false,
+            join_waker: None,
+            task_waker: None,
+        }),
+        cancel_requested: AtomicBool::new(false),
+    });
+
+    let handle = JoinHandle {
+        id: info.id(),
+        state: state.clone(),
+        taken: false,
+    };
+
+    (Joinable { future: Some(future), state, info }, handle)
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        // Safety: `future` is never moved out of the pinned `Joinable`,
+        // it is only dropped in place through `Pin::set`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        this.info.record_poll();
+
+        if future.is_none() {
+            return Poll::Ready(());
+        }
+
+        if this.state.cancel_requested.load(Ordering::Acquire) {
+            future.set(None);
+            this.state.finish(Err(JoinError::Cancelled));
+            return Poll::Ready(());
+        }
+
+        without_interrupts(|| {
+            let mut inner = this.state.inner.lock();
+            if !inner.task_waker.as_ref().is_some_and(|waker| waker.will_wake(context.waker())) {
+                inner.task_waker = Some(context.waker().clone());
+            }
+        });
+
+        let result = with_current_task(this.info.id(), || future.as_mut().as_pin_mut().unwrap().poll(context));
+        match result {
+            Poll::Ready(output) => {
+                future.set(None);
+                this.state.finish(Ok(output));
+                Poll::Ready(())
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<F: Future> Drop for Joinable<F> {
+    fn drop(&mut self) {
+        registry::unregister(self.info.id());
+
+        // The executor dropped the task before it finished.
+        self.state.finish(Err(JoinError::Cancelled));
+    }
+}
+
+/// Handle to await the output of a task or to cancel it.
+/// Dropping the handle detaches the task, it keeps running.
+#[must_use = "dropping a join handle detaches the task"]
+pub struct JoinHandle<T> {
+    id: TaskId,
+    state: Arc<JoinState<T>>,
+    /// Set once the result has been returned.
+    taken: bool,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Returns the name of the task, None if it has none or already finished.
+    pub fn name(&self) -> Option<alloc::string::String> {
+        registry::get(self.id).and_then(|info| info.name().map(Into::into))
+    }
+
+    /// Check if the task finished, was cancelled or dropped.
+    pub fn is_finished(&self) -> bool {
+        without_interrupts(|| self.state.inner.lock().finished)
+    }
+
+    /// Request the task to be cancelled. Its future is dropped
+    /// the next time the task is polled instead of being polled again.
+    pub fn abort(&self) {
+        self.state.cancel_requested.store(true, Ordering::Release);
+
+        let waker = without_interrupts(|| self.state.inner.lock().task_waker.take());
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Let the task run to completion without waiting for it.
+    pub fn detach(self) {}
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        assert!(!self.taken, "join handle polled after completion");
+
+        let result = without_interrupts(|| {
+            let mut inner = self.state.inner.lock();
+            match inner.result.take() {
+                Some(result) => Some(result),
+                None => {
+                    inner.join_waker = Some(context.waker().clone());
+                    None
+                },
+            }
+        });
+
+        match result {
+            Some(result) => {
+                self.taken = true;
+                Poll::Ready(result)
+            },
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("id", &self.id)
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
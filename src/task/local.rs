@@ -0,0 +1,85 @@
+use alloc::boxed::Box;
+use core::fmt;
+use super::{current_task, registry};
+
+/// Declare task-local storage keys, each task has its own value.
+/// The value is created on the first access within a task and dropped
+/// together with the task.
+///
+/// ```ignore
+/// task_local! {
+///     static REQUESTS: Cell<u32> = Cell::new(0);
+/// }
+///
+/// REQUESTS.with(|requests| requests.set(requests.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])* $vis static $name: $crate::task::local::LocalKey<$t> = {
+            fn init() -> $t {
+                $init
+            }
+            $crate::task::local::LocalKey::new(init)
+        };
+        $crate::task_local!($($rest)*);
+    };
+    () => {};
+}
+
+/// Error returned when a task-local value is accessed outside of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task-local value accessed outside of a task")
+    }
+}
+
+/// A key for task-local storage, created by [`task_local!`].
+pub struct LocalKey<T: Send + 'static> {
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    fn key(&'static self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Run `f` with the value of the current task.
+    /// # Safety
+    /// This function will panic if it is called outside of a task,
+    /// or from within `f` for the same key.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f).expect("task-local value accessed outside of a task")
+    }
+
+    /// Run `f` with the value of the current task.
+    /// Returns an error if no task is running on the current hart.
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        let info = current_task()
+            .and_then(registry::get)
+            .ok_or(AccessError)?;
+
+        // Take the value out while `f` runs, so that other keys can be
+        // accessed from within `f` without holding the lock.
+        let value = {
+            let mut locals = info.locals.lock();
+            let slot = locals
+                .entry(self.key())
+                .or_insert_with(|| Some(Box::new((self.init)())));
+            slot.take().expect("task-local value is already borrowed")
+        };
+
+        let result = f(value.downcast_ref::<T>().expect("task-local value has the wrong type"));
+
+        info.locals.lock().insert(self.key(), Some(value));
+        Ok(result)
+    }
+}
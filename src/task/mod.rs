@@ -1,22 +1,86 @@
-pub mod simple_executor;
+pub mod executor;
+pub mod join;
+pub mod local;
+pub mod registry;
+pub mod sync;
+pub mod smp_executor;
+
+pub use registry::print_tasks;
+pub use smp_executor::{spawn, spawn_local, spawn_with_affinity, Affinity, Builder};
 
 use alloc::boxed::Box;
+use core::fmt;
 use core::future::Future;
 use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::task::{Context, Poll};
+use crate::arch::consts::MAX_HARTS;
+use crate::smp::PerHart;
+
+/// Unique identifier of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// The task each hart is currently polling, 0 if none.
+static CURRENT_TASK: PerHart<AtomicU64> = PerHart::new([const { AtomicU64::new(0) }; MAX_HARTS]);
+
+/// Returns the task the given hart is currently polling.
+pub fn current_task_on(hart_id: usize) -> Option<TaskId> {
+    match CURRENT_TASK.get_for(hart_id).load(Ordering::Relaxed) {
+        0 => None,
+        id => Some(TaskId(id)),
+    }
+}
+
+/// Returns the task the current hart is polling.
+pub fn current_task() -> Option<TaskId> {
+    current_task_on(crate::arch::hart_id())
+}
+
+/// Run `f` with the given task marked as the current task of this hart.
+fn with_current_task<R>(id: TaskId, f: impl FnOnce() -> R) -> R {
+    let current = CURRENT_TASK.get();
+    let previous = current.swap(id.0, Ordering::Relaxed);
+    let result = f();
+    current.store(previous, Ordering::Relaxed);
+    result
+}
 
 pub struct Task {
+    id: TaskId,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
 impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+    fn with_id(id: TaskId, future: impl Future<Output = ()> + 'static) -> Self {
         Self {
+            id,
             future: Box::pin(future),
         }
     }
 
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        with_current_task(self.id, || self.future.as_mut().poll(context))
     }
-}
\ No newline at end of file
+}
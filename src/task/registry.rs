@@ -0,0 +1,112 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::{Mutex, RwLock};
+use crate::arch::hart_id;
+use super::TaskId;
+
+/// Values of the task-local keys of a task, indexed by the address of the key.
+/// None while the value is borrowed.
+pub(super) type LocalValues = BTreeMap<usize, Option<Box<dyn Any + Send>>>;
+
+/// Debugging information about a task that has not finished yet.
+pub struct TaskInfo {
+    id: TaskId,
+    name: Option<String>,
+    /// Whether the task is bound to the hart it was spawned on.
+    local: bool,
+    polls: AtomicU64,
+    /// The hart that polled the task last, `usize::MAX` if it never ran.
+    last_hart: AtomicUsize,
+    pub(super) locals: Mutex<LocalValues>,
+}
+
+impl TaskInfo {
+    pub(super) fn new(id: TaskId, name: Option<String>, local: bool) -> Self {
+        Self {
+            id,
+            name,
+            local,
+            polls: AtomicU64::new(0),
+            last_hart: AtomicUsize::new(usize::MAX),
+            locals: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(super) fn record_poll(&self) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.last_hart.store(hart_id(), Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of a task's state, returned by [`tasks`].
+#[derive(Debug, Clone)]
+pub struct TaskSnapshot {
+    pub id: TaskId,
+    pub name: Option<String>,
+    pub local: bool,
+    pub polls: u64,
+    pub last_hart: Option<usize>,
+}
+
+static TASKS: RwLock<BTreeMap<TaskId, Arc<TaskInfo>>> = RwLock::new(BTreeMap::new());
+
+pub(super) fn register(info: Arc<TaskInfo>) {
+    TASKS.write().insert(info.id, info);
+}
+
+pub(super) fn unregister(id: TaskId) {
+    TASKS.write().remove(&id);
+}
+
+/// Returns the information of a task that has not finished yet.
+pub fn get(id: TaskId) -> Option<Arc<TaskInfo>> {
+    TASKS.read().get(&id).cloned()
+}
+
+/// Returns a snapshot of all tasks that have not finished yet.
+pub fn tasks() -> Vec<TaskSnapshot> {
+    TASKS
+        .read()
+        .values()
+        .map(|info| {
+            let last_hart = info.last_hart.load(Ordering::Relaxed);
+            TaskSnapshot {
+                id: info.id,
+                name: info.name.clone(),
+                local: info.local,
+                polls: info.polls.load(Ordering::Relaxed),
+                last_hart: (last_hart != usize::MAX).then_some(last_hart),
+            }
+        })
+        .collect()
+}
+
+/// Print all tasks that have not finished yet.
+pub fn print_tasks() {
+    let tasks = tasks();
+
+    println!("+ Tasks ({}):", tasks.len());
+    for task in tasks {
+        println!(
+            "| {} {:<20} polls={} last hart={:?} {}",
+            task.id,
+            task.name.as_deref().unwrap_or("<unnamed>"),
+            task.polls,
+            task.last_hart,
+            if task.local { "local" } else { "shared" },
+        );
+    }
+}
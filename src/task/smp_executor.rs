@@ -0,0 +1,393 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use crate::arch::consts::MAX_HARTS;
+use crate::arch::hart_id;
+use crate::arch::trap::{disable_interrupts, enable_interrupts, without_interrupts};
+use crate::idle;
+use crate::smp::{self, HartMask, PerHart};
+use crate::thread;
+use super::executor::{Executor, Spawner};
+use super::join::{joinable, JoinHandle};
+use super::registry::TaskInfo;
+use super::{with_current_task, Task, TaskId};
+
+/// Maximum number of tasks polled from one source before
+/// the other sources get a chance to run.
+const BATCH_SIZE: usize = 16;
+
+/// On which hart a task should run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    /// The task may run on any hart.
+    Any,
+    /// The task starts out on the given hart but may be stolen by idle harts.
+    Prefer(usize),
+    /// The task only ever runs on the given hart.
+    Pinned(usize),
+}
+
+impl Affinity {
+    fn hart(self) -> Option<usize> {
+        match self {
+            Affinity::Any => None,
+            Affinity::Prefer(hart) | Affinity::Pinned(hart) => Some(hart),
+        }
+    }
+}
+
+type SendFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A task that can move between harts.
+struct SharedTask {
+    id: TaskId,
+    affinity: Affinity,
+    /// None once the task has finished.
+    future: Mutex<Option<SendFuture>>,
+    /// Set while the task sits in a run queue, so that it is queued at most once.
+    scheduled: AtomicBool,
+}
+
+type TaskRef = Arc<SharedTask>;
+
+impl SharedTask {
+    fn is_stealable(&self) -> bool {
+        !matches!(self.affinity, Affinity::Pinned(_))
+    }
+
+    /// Put the task into a run queue, unless it is queued already.
+    fn schedule(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let current = hart_id();
+        let workers = worker_harts();
+
+        // Tasks without affinity stay on the hart that wakes them if it runs
+        // the executor, otherwise they are handed to the global injector.
+        let target = self
+            .affinity
+            .hart()
+            .or_else(|| workers.contains(current).then_some(current));
+
+        match target {
+            Some(hart) => {
+                without_interrupts(|| RUN_QUEUES.get_for(hart).lock().push_back(self.clone()));
+                if hart != current {
+                    smp::kick(HartMask::single(hart));
+                } else {
+                    wake_idle_hart();
+                }
+            },
+            None => {
+                without_interrupts(|| INJECTOR.lock().push_back(self.clone()));
+                wake_idle_hart();
+            },
+        }
+    }
+
+    /// Poll the task once on the current hart.
+    fn run(self: Arc<Self>) {
+        // Another hart may still be polling the task, if it was woken
+        // and stolen during the poll. Try again later in that case.
+        let Some(mut future) = self.future.try_lock() else {
+            self.scheduled.store(false, Ordering::Release);
+            self.schedule();
+            return;
+        };
+        let Some(inner) = future.as_mut() else {
+            // Woken after it finished.
+            return;
+        };
+
+        // Clear the flag before polling, so that a wakeup during the poll queues it again.
+        self.scheduled.store(false, Ordering::Release);
+
+        let waker = Waker::from(self.clone());
+        let mut context = Context::from_waker(&waker);
+        if let Poll::Ready(()) = with_current_task(self.id, || inner.as_mut().poll(&mut context)) {
+            *future = None;
+        }
+    }
+}
+
+impl Wake for SharedTask {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
+
+/// Run queue of every hart. Queues are also accessed by wakers
+/// in trap context, so they must only be locked with interrupts disabled.
+static RUN_QUEUES: PerHart<Mutex<VecDeque<TaskRef>>> =
+    PerHart::new([const { Mutex::new(VecDeque::new()) }; MAX_HARTS]);
+/// Tasks that are not bound to a hart, taken by whichever hart gets to them first.
+static INJECTOR: Mutex<VecDeque<TaskRef>> = Mutex::new(VecDeque::new());
+
+/// Harts that run the executor.
+static WORKER_HARTS: AtomicUsize = AtomicUsize::new(0);
+/// Harts that run the executor and are sleeping because they had no work.
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+fn worker_harts() -> HartMask {
+    HartMask::from_bits(WORKER_HARTS.load(Ordering::Acquire))
+}
+
+/// Harts whose executor is sleeping because it had no work.
+pub fn idle_harts() -> HartMask {
+    HartMask::from_bits(IDLE_HARTS.load(Ordering::Acquire))
+}
+
+/// Wake up one idle hart other than the current one, so it can steal work.
+fn wake_idle_hart() {
+    if let Some(hart) = idle_harts().without(hart_id()).iter().next() {
+        smp::kick(HartMask::single(hart));
+    }
+}
+
+/// The executor for tasks that are not `Send` of a hart.
+/// It is only ever accessed by the hart it belongs to.
+struct LocalExecutor {
+    /// The executor until [`run`] takes it over.
+    executor: RefCell<Option<Executor>>,
+    spawner: RefCell<Option<Spawner>>,
+}
+
+// Safety: Every hart only accesses its own instance through `PerHart::get`.
+unsafe impl Sync for LocalExecutor {}
+
+static LOCAL_EXECUTORS: PerHart<LocalExecutor> = PerHart::new([const {
+    LocalExecutor {
+        executor: RefCell::new(None),
+        spawner: RefCell::new(None),
+    }
+}; MAX_HARTS]);
+
+fn spawn_shared(id: TaskId, future: impl Future<Output = ()> + Send + 'static, affinity: Affinity) {
+    if let Some(hart) = affinity.hart() {
+        assert!(hart < MAX_HARTS, "invalid hart {} in task affinity", hart);
+    }
+
+    let task = Arc::new(SharedTask {
+        id,
+        affinity,
+        future: Mutex::new(Some(Box::pin(future))),
+        scheduled: AtomicBool::new(false),
+    });
+    task.schedule();
+}
+
+/// Configures a task before spawning it.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    name: Option<String>,
+    affinity: Affinity,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            affinity: Affinity::Any,
+        }
+    }
+
+    /// Name the task, it shows up in the task list.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set on which hart the task should run. Ignored by [`Builder::spawn_local`].
+    pub fn affinity(mut self, affinity: Affinity) -> Self {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Spawn a task that may move between harts.
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = TaskId::new();
+        let local = matches!(self.affinity, Affinity::Pinned(_));
+        let (future, handle) = joinable(future, TaskInfo::new(id, self.name, local));
+        spawn_shared(id, future, self.affinity);
+        handle
+    }
+
+    /// Spawn a task that is not `Send` on the current hart.
+    /// It never moves to another hart and only runs once the hart calls [`run`].
+    pub fn spawn_local<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let id = TaskId::new();
+        let (future, handle) = joinable(future, TaskInfo::new(id, self.name, true));
+
+        let local = LOCAL_EXECUTORS.get();
+        let mut spawner = local.spawner.borrow_mut();
+        let spawner = spawner.get_or_insert_with(|| {
+            let executor = Executor::new();
+            let spawner = executor.spawner();
+            *local.executor.borrow_mut() = Some(executor);
+            spawner
+        });
+        spawner.spawn_task(Task::with_id(id, future));
+
+        handle
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Spawn a task that may run on any hart.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Builder::new().spawn(future)
+}
+
+/// Spawn a task with a hint on which hart it should run.
+pub fn spawn_with_affinity<F>(future: F, affinity: Affinity) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Builder::new().affinity(affinity).spawn(future)
+}
+
+/// Spawn a task that is not `Send` on the current hart.
+/// It never moves to another hart and only runs once the hart calls [`run`].
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    Builder::new().spawn_local(future)
+}
+
+/// Take a task from the run queue of the current hart,
+/// the global injector or the run queue of another hart, in that order.
+fn next_task() -> Option<TaskRef> {
+    let current = hart_id();
+
+    if let Some(task) = without_interrupts(|| RUN_QUEUES.get().lock().pop_front()) {
+        return Some(task);
+    }
+
+    if let Some(task) = without_interrupts(|| INJECTOR.lock().pop_front()) {
+        return Some(task);
+    }
+
+    for victim in worker_harts().without(current).iter() {
+        if let Some(task) = steal_from(victim) {
+            return Some(task);
+        }
+    }
+
+    None
+}
+
+/// Move half of the stealable tasks of another hart to the current hart.
+/// Returns one of the stolen tasks.
+fn steal_from(victim: usize) -> Option<TaskRef> {
+    let mut stolen = without_interrupts(|| {
+        let mut queue = RUN_QUEUES.get_for(victim).lock();
+        let count = queue.iter().filter(|task| task.is_stealable()).count().div_ceil(2);
+
+        // Take from the back, the victim works on the front.
+        let mut stolen = VecDeque::new();
+        let mut index = queue.len();
+        while index > 0 && stolen.len() < count {
+            index -= 1;
+            if queue[index].is_stealable() {
+                stolen.push_front(queue.remove(index).unwrap());
+            }
+        }
+        stolen
+    });
+
+    let task = stolen.pop_front()?;
+    if !stolen.is_empty() {
+        without_interrupts(|| RUN_QUEUES.get().lock().extend(stolen));
+    }
+    Some(task)
+}
+
+/// Check if the current hart has work without stealing.
+fn has_work(local: &Executor) -> bool {
+    local.has_ready_tasks()
+        || without_interrupts(|| !RUN_QUEUES.get().lock().is_empty() || !INJECTOR.lock().is_empty())
+        || thread::has_runnable()
+}
+
+/// Run tasks on the current hart forever.
+/// Should be called on every hart once it finished booting.
+pub fn run() -> ! {
+    let current = hart_id();
+    let mut local = {
+        let slot = LOCAL_EXECUTORS.get();
+        let local = slot.executor.borrow_mut().take().unwrap_or_else(Executor::new);
+        slot.spawner.borrow_mut().get_or_insert_with(|| local.spawner());
+        local
+    };
+    WORKER_HARTS.fetch_or(1 << current, Ordering::AcqRel);
+
+    loop {
+        // Alternate between tasks bound to this hart and shared tasks,
+        // so neither of them can starve the other.
+        let mut polled = local.poll_ready(BATCH_SIZE);
+        while polled < 2 * BATCH_SIZE {
+            let Some(task) = next_task() else {
+                break;
+            };
+            task.run();
+            polled += 1;
+        }
+
+        if polled > 0 {
+            continue;
+        }
+
+        // The executor runs in the boot thread of the hart,
+        // let kernel threads have the hart while it has nothing to do.
+        if thread::has_runnable() {
+            thread::yield_now();
+            continue;
+        }
+
+        // Announce that this hart is going to sleep before checking for work
+        // a last time. Anybody queueing work after the check sees the flag and
+        // sends an IPI, which ends the sleep.
+        disable_interrupts();
+        IDLE_HARTS.fetch_or(1 << current, Ordering::AcqRel);
+        if has_work(&local) {
+            enable_interrupts();
+        } else {
+            idle::idle();
+        }
+        IDLE_HARTS.fetch_and(!(1 << current), Ordering::AcqRel);
+    }
+}
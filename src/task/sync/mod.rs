@@ -0,0 +1,12 @@
+pub mod mpsc;
+pub mod mutex;
+pub mod notify;
+pub mod oneshot;
+pub mod rwlock;
+pub mod semaphore;
+mod wait_queue;
+
+pub use mutex::Mutex;
+pub use notify::{Event, Notify};
+pub use rwlock::RwLock;
+pub use semaphore::Semaphore;
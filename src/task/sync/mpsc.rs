@@ -0,0 +1,270 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+use core::future::poll_fn;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex as SpinMutex;
+use super::semaphore::Semaphore;
+use super::wait_queue::locked;
+
+/// Error returned when sending to a channel whose receiver is gone.
+/// Contains the value that could not be sent.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+/// Error returned by [`Sender::try_send`].
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel has no free slot right now.
+    Full(T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver_dropped: bool,
+    receiver_waker: Option<Waker>,
+}
+
+struct Channel<T> {
+    state: SpinMutex<State<T>>,
+    /// Free slots of a bounded channel, None for unbounded channels.
+    slots: Option<Semaphore>,
+}
+
+impl<T> Channel<T> {
+    fn new(slots: Option<Semaphore>) -> Arc<Self> {
+        Arc::new(Self {
+            state: SpinMutex::new(State {
+                queue: VecDeque::new(),
+                senders: 1,
+                receiver_dropped: false,
+                receiver_waker: None,
+            }),
+            slots,
+        })
+    }
+
+    /// Queue a value, the caller already reserved a slot if the channel is bounded.
+    fn push(&self, value: T) -> Result<(), SendError<T>> {
+        let waker = locked(&self.state, |state| {
+            if state.receiver_dropped {
+                return Err(SendError(value));
+            }
+
+            state.queue.push_back(value);
+            Ok(state.receiver_waker.take())
+        })?;
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn add_sender(&self) {
+        locked(&self.state, |state| state.senders += 1);
+    }
+
+    fn drop_sender(&self) {
+        let waker = locked(&self.state, |state| {
+            state.senders -= 1;
+            if state.senders == 0 {
+                state.receiver_waker.take()
+            } else {
+                None
+            }
+        });
+
+        // Let the receiver see that the channel is closed.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Create a channel that holds up to `capacity` values.
+/// Senders wait while the channel is full.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+
+    let channel = Channel::new(Some(Semaphore::new(capacity)));
+    (Sender { channel: channel.clone() }, Receiver { channel })
+}
+
+/// Create a channel without a limit on the number of queued values.
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let channel = Channel::new(None);
+    (UnboundedSender { channel: channel.clone() }, Receiver { channel })
+}
+
+/// Sends values into a bounded [`channel`].
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    fn slots(&self) -> &Semaphore {
+        self.channel.slots.as_ref().expect("bounded channel without slots")
+    }
+
+    /// Send a value, waiting for a free slot if the channel is full.
+    /// Returns the value back if the receiver is gone.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self.slots().acquire().await {
+            // The slot is given back by the receiver once it took the value.
+            Ok(permit) => permit.forget(),
+            Err(_) => return Err(SendError(value)),
+        }
+
+        self.channel.push(value)
+    }
+
+    /// Send a value if the channel has a free slot right now.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.slots().is_closed() {
+            return Err(TrySendError::Closed(value));
+        }
+
+        match self.slots().try_acquire() {
+            Some(permit) => permit.forget(),
+            None => return Err(TrySendError::Full(value)),
+        }
+
+        self.channel.push(value).map_err(|SendError(value)| TrySendError::Closed(value))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        locked(&self.channel.state, |state| state.receiver_dropped)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_sender();
+        Self { channel: self.channel.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.channel.drop_sender();
+    }
+}
+
+/// Sends values into an [`unbounded_channel`].
+pub struct UnboundedSender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Send a value without waiting. Returns it back if the receiver is gone.
+    /// Can be called from interrupt handlers.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.channel.push(value)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        locked(&self.channel.state, |state| state.receiver_dropped)
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_sender();
+        Self { channel: self.channel.clone() }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.channel.drop_sender();
+    }
+}
+
+/// Receives the values of a channel in the order they were sent.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Wait for the next value.
+    /// Returns None once all senders are gone and the channel is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|context| self.poll_recv(context)).await
+    }
+
+    pub fn poll_recv(&mut self, context: &mut Context<'_>) -> Poll<Option<T>> {
+        let result = locked(&self.channel.state, |state| {
+            if let Some(value) = state.queue.pop_front() {
+                return Poll::Ready(Some(value));
+            }
+            if state.senders == 0 {
+                return Poll::Ready(None);
+            }
+
+            state.receiver_waker = Some(context.waker().clone());
+            Poll::Pending
+        });
+
+        if let Poll::Ready(Some(_)) = result {
+            self.free_slot();
+        }
+        result
+    }
+
+    /// Take the next value if one is queued right now.
+    pub fn try_recv(&mut self) -> Option<T> {
+        let value = locked(&self.channel.state, |state| state.queue.pop_front());
+        if value.is_some() {
+            self.free_slot();
+        }
+        value
+    }
+
+    /// Close the channel, further sends fail. Queued values can still be received.
+    pub fn close(&mut self) {
+        locked(&self.channel.state, |state| state.receiver_dropped = true);
+        if let Some(slots) = &self.channel.slots {
+            slots.close();
+        }
+    }
+
+    fn free_slot(&self) {
+        if let Some(slots) = &self.channel.slots {
+            slots.add_permits(1);
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+        // Drop the queued values outside of the channel lock.
+        let values = locked(&self.channel.state, |state| core::mem::take(&mut state.queue));
+        drop(values);
+    }
+}
@@ -0,0 +1,222 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use spin::Mutex as SpinMutex;
+use super::wait_queue::{locked, WaitQueue, Waiter};
+
+struct NotifyState {
+    /// A notification that arrived while nobody was waiting.
+    permit: bool,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+/// Wakes up tasks waiting for a notification.
+///
+/// [`Notify::notify_one`] wakes a single waiting task. If no task is
+/// waiting, the notification is stored and the next call to
+/// [`Notify::notified`] completes immediately.
+pub struct Notify {
+    state: SpinMutex<NotifyState>,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            state: SpinMutex::new(NotifyState {
+                permit: false,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Wait for a notification.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { notify: self, waiter: None }
+    }
+
+    /// Wake the task that has been waiting the longest,
+    /// or store the notification if no task is waiting.
+    /// Can be called from interrupt handlers.
+    pub fn notify_one(&self) {
+        let waiter = locked(&self.state, |state| {
+            let waiter = state.waiters.pop_front();
+            if waiter.is_none() {
+                state.permit = true;
+            }
+            waiter
+        });
+
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+
+    /// Wake all tasks that are waiting right now. No notification is stored.
+    pub fn notify_waiters(&self) {
+        let waiters = locked(&self.state, |state| core::mem::take(&mut state.waiters));
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`Notify::notified`].
+#[must_use = "futures do nothing unless awaited"]
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        if let Some(waiter) = &self.waiter {
+            waiter.register(context.waker());
+            if waiter.is_woken() {
+                self.waiter = None;
+                return Poll::Ready(());
+            }
+            return Poll::Pending;
+        }
+
+        let waiter = locked(&self.notify.state, |state| {
+            if state.permit {
+                state.permit = false;
+                return None;
+            }
+
+            let waiter = Waiter::new();
+            waiter.register(context.waker());
+            state.waiters.push_back(waiter.clone());
+            Some(waiter)
+        });
+
+        match waiter {
+            Some(waiter) => {
+                self.waiter = Some(waiter);
+                Poll::Pending
+            },
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+
+        let removed = locked(&self.notify.state, |state| {
+            let len = state.waiters.len();
+            state.waiters.retain(|queued| !Arc::ptr_eq(queued, &waiter));
+            len != state.waiters.len()
+        });
+
+        // Pass on a notification that was never consumed.
+        if !removed && waiter.is_woken() {
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// A flag tasks can wait for. Once set, it stays set
+/// and all waiting tasks complete until it is cleared again.
+pub struct Event {
+    set: AtomicBool,
+    waiters: WaitQueue,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Self {
+            set: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Set the flag and wake all waiting tasks.
+    /// Can be called from interrupt handlers.
+    pub fn set(&self) {
+        self.set.store(true, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    pub fn clear(&self) {
+        self.set.store(false, Ordering::Release);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    /// Wait until the flag is set.
+    pub fn wait(&self) -> EventWait<'_> {
+        EventWait { event: self, waiter: None }
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`Event::wait`].
+#[must_use = "futures do nothing unless awaited"]
+pub struct EventWait<'a> {
+    event: &'a Event,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for EventWait<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        if self.event.is_set() {
+            if let Some(waiter) = self.waiter.take() {
+                self.event.waiters.remove(&waiter);
+            }
+            return Poll::Ready(());
+        }
+
+        match &self.waiter {
+            // Still queued, only the waker may have changed.
+            Some(waiter) if !waiter.is_woken() => waiter.register(context.waker()),
+            // Not queued yet, or woken by a `set` that was cleared again before this poll.
+            _ => {
+                let waiter = Waiter::new();
+                waiter.register(context.waker());
+                self.event.waiters.push(waiter.clone());
+                self.waiter = Some(waiter);
+
+                // The flag may have been set before the waiter was queued.
+                if self.event.is_set() {
+                    let waiter = self.waiter.take().unwrap();
+                    self.event.waiters.remove(&waiter);
+                    return Poll::Ready(());
+                }
+            },
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for EventWait<'_> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            self.event.waiters.remove(&waiter);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex as SpinMutex;
+use super::wait_queue::locked;
+
+/// Error returned by a [`Receiver`] if the sender was dropped without sending a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sender dropped without sending a value")
+    }
+}
+
+struct State<T> {
+    value: Option<T>,
+    /// Set once the sender sent a value or was dropped.
+    sender_done: bool,
+    receiver_dropped: bool,
+    receiver_waker: Option<Waker>,
+}
+
+/// Create a channel that transfers a single value.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let state = Arc::new(SpinMutex::new(State {
+        value: None,
+        sender_done: false,
+        receiver_dropped: false,
+        receiver_waker: None,
+    }));
+
+    (Sender { state: state.clone() }, Receiver { state })
+}
+
+/// Sends the value of a [`channel`].
+pub struct Sender<T> {
+    state: Arc<SpinMutex<State<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Send the value. Returns it back if the receiver is gone.
+    /// Can be called from interrupt handlers.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let waker = locked(&self.state, |state| {
+            if state.receiver_dropped {
+                return Err(value);
+            }
+
+            state.value = Some(value);
+            state.sender_done = true;
+            Ok(state.receiver_waker.take())
+        })?;
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Check if the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        locked(&self.state, |state| state.receiver_dropped)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = locked(&self.state, |state| {
+            state.sender_done = true;
+            state.receiver_waker.take()
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Receives the value of a [`channel`] when awaited.
+pub struct Receiver<T> {
+    state: Arc<SpinMutex<State<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Take the value if it has been sent already.
+    pub fn try_recv(&mut self) -> Option<T> {
+        locked(&self.state, |state| state.value.take())
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        locked(&self.state, |state| {
+            if let Some(value) = state.value.take() {
+                return Poll::Ready(Ok(value));
+            }
+            if state.sender_done {
+                return Poll::Ready(Err(RecvError));
+            }
+
+            state.receiver_waker = Some(context.waker().clone());
+            Poll::Pending
+        })
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        locked(&self.state, |state| {
+            state.receiver_dropped = true;
+            state.receiver_waker = None;
+        });
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use spin::Mutex as SpinMutex;
+use super::wait_queue::{locked, Waiter};
+
+/// Error returned when acquiring permits of a closed [`Semaphore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcquireError;
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "semaphore closed")
+    }
+}
+
+struct State {
+    permits: usize,
+    closed: bool,
+    /// Waiting tasks with the number of permits they need.
+    /// Permits are handed out strictly in order, so large requests do not starve.
+    waiters: VecDeque<(usize, Arc<Waiter>)>,
+}
+
+impl State {
+    /// Hand out permits to the waiters at the front of the queue.
+    /// Returns the waiters that have to be woken.
+    fn grant(&mut self) -> VecDeque<Arc<Waiter>> {
+        let mut granted = VecDeque::new();
+        while let Some(&(needed, _)) = self.waiters.front() {
+            if needed > self.permits {
+                break;
+            }
+
+            self.permits -= needed;
+            granted.push_back(self.waiters.pop_front().unwrap().1);
+        }
+        granted
+    }
+}
+
+/// An async counting semaphore with FIFO fairness.
+pub struct Semaphore {
+    state: SpinMutex<State>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: SpinMutex::new(State {
+                permits,
+                closed: false,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        locked(&self.state, |state| state.permits)
+    }
+
+    /// Wait for a permit.
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Wait until `permits` permits are available and take all of them at once.
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    /// Take a permit if one is available right now.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Take `permits` permits if they are available right now.
+    pub fn try_acquire_many(&self, permits: usize) -> Option<SemaphorePermit<'_>> {
+        locked(&self.state, |state| {
+            // Do not overtake tasks that are already waiting.
+            if state.closed || !state.waiters.is_empty() || state.permits < permits {
+                return None;
+            }
+
+            state.permits -= permits;
+            Some(SemaphorePermit { semaphore: self, permits })
+        })
+    }
+
+    /// Return permits to the semaphore and wake the tasks that can now take them.
+    pub fn add_permits(&self, permits: usize) {
+        let granted = locked(&self.state, |state| {
+            state.permits += permits;
+            state.grant()
+        });
+
+        for waiter in granted {
+            waiter.wake();
+        }
+    }
+
+    /// Close the semaphore. All pending and future acquires fail.
+    pub fn close(&self) {
+        let waiters = locked(&self.state, |state| {
+            state.closed = true;
+            core::mem::take(&mut state.waiters)
+        });
+
+        for (_, waiter) in waiters {
+            waiter.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        locked(&self.state, |state| state.closed)
+    }
+
+    /// Stop waiting. Permits that were granted in the meantime are returned.
+    fn cancel(&self, permits: usize, waiter: &Arc<Waiter>) {
+        let granted = locked(&self.state, |state| {
+            let len = state.waiters.len();
+            state.waiters.retain(|(_, queued)| !Arc::ptr_eq(queued, waiter));
+            if len == state.waiters.len() && !state.closed {
+                // Already granted, give the permits back.
+                state.permits += permits;
+            }
+
+            // Removing the front waiter may allow the next ones to proceed.
+            state.grant()
+        });
+
+        for waiter in granted {
+            waiter.wake();
+        }
+    }
+}
+
+/// Future returned by [`Semaphore::acquire`].
+#[must_use = "futures do nothing unless awaited"]
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Result<SemaphorePermit<'a>, AcquireError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let permits = self.permits;
+
+        if let Some(waiter) = &self.waiter {
+            waiter.register(context.waker());
+            if !waiter.is_woken() {
+                return Poll::Pending;
+            }
+
+            self.waiter = None;
+            if semaphore.is_closed() {
+                return Poll::Ready(Err(AcquireError));
+            }
+            return Poll::Ready(Ok(SemaphorePermit { semaphore, permits }));
+        }
+
+        let waiter = locked(&semaphore.state, |state| {
+            if state.closed {
+                return Err(AcquireError);
+            }
+
+            if state.waiters.is_empty() && state.permits >= permits {
+                state.permits -= permits;
+                return Ok(None);
+            }
+
+            let waiter = Waiter::new();
+            waiter.register(context.waker());
+            state.waiters.push_back((permits, waiter.clone()));
+            Ok(Some(waiter))
+        });
+
+        match waiter {
+            Err(error) => Poll::Ready(Err(error)),
+            Ok(None) => Poll::Ready(Ok(SemaphorePermit { semaphore, permits })),
+            Ok(Some(waiter)) => {
+                self.waiter = Some(waiter);
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            self.semaphore.cancel(self.permits, &waiter);
+        }
+    }
+}
+
+/// Permits taken from a [`Semaphore`], returned when dropped.
+#[must_use = "the permits are returned immediately if the permit is dropped"]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Keep the permits taken for good.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
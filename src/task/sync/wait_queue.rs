@@ -0,0 +1,92 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Waker;
+use spin::Mutex;
+use crate::arch::trap::without_interrupts;
+
+/// Lock a spin lock with interrupts disabled, so that
+/// an interrupt handler on the same hart can not deadlock on it.
+pub(super) fn locked<T, R>(mutex: &Mutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    without_interrupts(|| f(&mut mutex.lock()))
+}
+
+/// A task waiting for something to happen.
+pub(super) struct Waiter {
+    waker: Mutex<Option<Waker>>,
+    woken: AtomicBool,
+}
+
+impl Waiter {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            waker: Mutex::new(None),
+            woken: AtomicBool::new(false),
+        })
+    }
+
+    /// Remember the waker to wake, replacing the previous one.
+    pub fn register(&self, waker: &Waker) {
+        locked(&self.waker, |current| {
+            if !current.as_ref().is_some_and(|current| current.will_wake(waker)) {
+                *current = Some(waker.clone());
+            }
+        });
+    }
+
+    pub fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Acquire)
+    }
+
+    pub fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(waker) = locked(&self.waker, |waker| waker.take()) {
+            waker.wake();
+        }
+    }
+}
+
+/// A FIFO queue of waiting tasks.
+pub(super) struct WaitQueue {
+    waiters: Mutex<VecDeque<Arc<Waiter>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { waiters: Mutex::new(VecDeque::new()) }
+    }
+
+    pub fn push(&self, waiter: Arc<Waiter>) {
+        locked(&self.waiters, |waiters| waiters.push_back(waiter));
+    }
+
+    /// Remove a waiter that is no longer interested.
+    /// Returns false if it was not queued, for example because it was woken already.
+    pub fn remove(&self, waiter: &Arc<Waiter>) -> bool {
+        locked(&self.waiters, |waiters| {
+            let len = waiters.len();
+            waiters.retain(|queued| !Arc::ptr_eq(queued, waiter));
+            len != waiters.len()
+        })
+    }
+
+    /// Wake the waiter that has been waiting the longest.
+    /// Returns false if nobody was waiting.
+    pub fn wake_one(&self) -> bool {
+        match locked(&self.waiters, |waiters| waiters.pop_front()) {
+            Some(waiter) => {
+                waiter.wake();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Wake all waiters.
+    pub fn wake_all(&self) {
+        let waiters = locked(&self.waiters, core::mem::take);
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
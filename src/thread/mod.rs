@@ -0,0 +1,332 @@
+pub mod policy;
+pub mod realtime;
+pub mod scheduler;
+
+pub use scheduler::{current, has_runnable, yield_now};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicI8, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::arch::context::Context;
+use crate::arch::stack::KernelStack;
+use crate::arch::trap::without_interrupts;
+use crate::time::{ticks_to_duration, timer, Duration, Instant};
+use realtime::RtTask;
+
+/// Number of pages of a kernel thread stack.
+pub const STACK_PAGES: usize = 16;
+
+/// Highest priority nice value.
+pub const NICE_MIN: i8 = -20;
+/// Lowest priority nice value.
+pub const NICE_MAX: i8 = 19;
+/// Number of distinct nice values.
+pub const NICE_LEVELS: usize = (NICE_MAX - NICE_MIN) as usize + 1;
+
+/// Unique identifier of a kernel thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Waiting in the run queue.
+    Ready,
+    /// Running on a hart.
+    Running,
+    /// Waiting for a wakeup, for example from a timer or an exiting thread.
+    Blocked,
+    /// The entry function returned.
+    Exited,
+}
+
+/// A kernel thread.
+pub struct Thread {
+    id: ThreadId,
+    name: Option<String>,
+    /// Set for the boot thread of a hart. Boot threads run the async
+    /// executor of their hart, so they never leave it and never block.
+    boot_hart: Option<usize>,
+    /// Scheduling priority between [`NICE_MIN`] and [`NICE_MAX`], lower runs first.
+    nice: AtomicI8,
+    /// Timebase ticks the thread has been running.
+    cpu_time: AtomicU64,
+    /// Virtual runtime used by the fair scheduler.
+    vruntime: AtomicU64,
+    /// The hart the thread ran on last.
+    last_hart: AtomicUsize,
+    /// Set for real-time threads, which are scheduled by deadline.
+    realtime: Option<Arc<RtTask>>,
+    /// Only locked with interrupts disabled, threads are woken from trap handlers.
+    state: Mutex<ThreadState>,
+    /// Set while a hart runs on the stack of the thread. A hart that picks
+    /// the thread waits until the previous hart switched away from it.
+    on_cpu: AtomicBool,
+    /// Saved registers, only accessed by the hart switching the thread.
+    context: UnsafeCell<Context>,
+    /// None for boot threads, which run on their hart stack.
+    stack: Mutex<Option<KernelStack>>,
+    entry: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    /// Threads waiting for this thread to exit.
+    joiners: Mutex<Vec<Arc<Thread>>>,
+}
+
+// The context is only accessed while switching, see `scheduler::schedule`.
+unsafe impl Sync for Thread {}
+
+impl Thread {
+    fn new(
+        name: Option<String>,
+        boot_hart: Option<usize>,
+        realtime: Option<Arc<RtTask>>,
+        stack: Option<KernelStack>,
+        context: Context,
+    ) -> Arc<Self> {
+        let thread = Arc::new(Self {
+            id: ThreadId::new(),
+            name,
+            boot_hart,
+            nice: AtomicI8::new(0),
+            cpu_time: AtomicU64::new(0),
+            vruntime: AtomicU64::new(0),
+            last_hart: AtomicUsize::new(boot_hart.or(realtime.as_ref().map(|task| task.hart())).unwrap_or(0)),
+            realtime,
+            state: Mutex::new(ThreadState::Ready),
+            on_cpu: AtomicBool::new(false),
+            context: UnsafeCell::new(context),
+            stack: Mutex::new(stack),
+            entry: Mutex::new(None),
+            joiners: Mutex::new(Vec::new()),
+        });
+
+        without_interrupts(|| THREADS.lock().insert(thread.id, thread.clone()));
+        thread
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn state(&self) -> ThreadState {
+        without_interrupts(|| *self.state.lock())
+    }
+
+    pub fn nice(&self) -> i8 {
+        self.nice.load(Ordering::Relaxed)
+    }
+
+    /// Change the priority of the thread, clamped to [`NICE_MIN`]..=[`NICE_MAX`].
+    /// Queued threads keep their place until they run again.
+    pub fn set_nice(&self, nice: i8) {
+        self.nice.store(nice.clamp(NICE_MIN, NICE_MAX), Ordering::Relaxed);
+    }
+
+    /// Total time the thread has been running, up to its last switch or tick.
+    pub fn cpu_time(&self) -> Duration {
+        ticks_to_duration(self.cpu_time.load(Ordering::Relaxed))
+    }
+
+    /// The hart the thread is running on or ran on last.
+    pub fn last_hart(&self) -> usize {
+        self.last_hart.load(Ordering::Relaxed)
+    }
+
+    /// The real-time parameters and counters, None for normal threads.
+    pub fn realtime(&self) -> Option<&RtTask> {
+        self.realtime.as_deref()
+    }
+
+    /// Check if this is the boot thread of a hart.
+    pub fn is_boot_thread(&self) -> bool {
+        self.boot_hart.is_some()
+    }
+
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("state", &self.state())
+            .field("nice", &self.nice())
+            .field("cpu_time", &self.cpu_time())
+            .finish()
+    }
+}
+
+/// All threads that have not exited yet. Only locked with interrupts disabled.
+static THREADS: Mutex<BTreeMap<ThreadId, Arc<Thread>>> = Mutex::new(BTreeMap::new());
+
+fn unregister(id: ThreadId) {
+    without_interrupts(|| THREADS.lock().remove(&id));
+}
+
+/// Print all threads that have not exited yet.
+pub fn print_threads() {
+    let threads: Vec<_> = without_interrupts(|| THREADS.lock().values().cloned().collect());
+
+    println!("+ Threads ({}, {} scheduler):", threads.len(), scheduler::name());
+    for thread in threads {
+        println!(
+            "| {} {:<12} {:?} on hart {}, nice {}, cpu time {:?}",
+            thread.id,
+            thread.name().unwrap_or("<unnamed>"),
+            thread.state(),
+            thread.last_hart(),
+            thread.nice(),
+            thread.cpu_time()
+        );
+    }
+}
+
+/// Configures a new thread before spawning it.
+pub struct Builder {
+    name: Option<String>,
+    nice: i8,
+    /// Set by [`realtime::spawn_periodic`].
+    realtime: Option<Arc<RtTask>>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self { name: None, nice: 0, realtime: None }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// See [`Thread::set_nice`].
+    pub fn nice(mut self, nice: i8) -> Self {
+        self.nice = nice;
+        self
+    }
+
+    /// Spawn a thread running `f`.
+    ///
+    /// # Safety
+    /// Panics if there is no memory left for the thread stack.
+    pub fn spawn<F, T>(self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let stack = KernelStack::new(STACK_PAGES).expect("out of memory for thread stack");
+        let context = Context::new(scheduler::thread_entry, stack.top());
+        let thread = Thread::new(self.name, None, self.realtime, Some(stack), context);
+        thread.set_nice(self.nice);
+
+        let result = Arc::new(Mutex::new(None));
+        let slot = result.clone();
+        *thread.entry.lock() = Some(Box::new(move || {
+            let value = f();
+            *slot.lock() = Some(value);
+        }));
+
+        scheduler::wake_new(thread.clone());
+        JoinHandle { thread, result }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Spawn a kernel thread running `f`.
+/// See [`Builder`] to configure the thread.
+pub fn spawn_thread<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new().spawn(f)
+}
+
+/// Handle to a spawned thread, used to wait for its result.
+/// Dropping the handle detaches the thread.
+pub struct JoinHandle<T> {
+    thread: Arc<Thread>,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn thread(&self) -> &Arc<Thread> {
+        &self.thread
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.state() == ThreadState::Exited
+    }
+
+    /// Block the current thread until the thread exits and return its result.
+    ///
+    /// # Safety
+    /// Panics if called from a boot thread, see [`scheduler::block_until`].
+    pub fn join(self) -> T {
+        while !self.is_finished() {
+            scheduler::block_until(|current| {
+                let mut joiners = self.thread.joiners.lock();
+                if *self.thread.state.lock() == ThreadState::Exited {
+                    return false;
+                }
+
+                joiners.push(current.clone());
+                true
+            });
+        }
+
+        self.result.lock().take().expect("joined thread exited without a result")
+    }
+}
+
+/// Block the current thread for at least the given duration.
+///
+/// # Safety
+/// Panics if called from a boot thread, see [`scheduler::block_until`].
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now().saturating_add(duration);
+
+    while Instant::now() < deadline {
+        let mut timer = None;
+        scheduler::block_until(|current| {
+            let thread = current.clone();
+            timer = Some(timer::oneshot_at(deadline, move || scheduler::wake(&thread)));
+            true
+        });
+
+        // Woken before the deadline, the next iteration arms a new timer.
+        if let Some(timer) = timer {
+            timer.cancel();
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
+use crate::thread::{Thread, ThreadId, NICE_MIN};
+use crate::time::{duration_to_ticks, Duration};
+use super::Scheduler;
+
+/// Period in which every queued thread should run once.
+const SCHED_LATENCY: Duration = Duration::from_millis(20);
+/// Shortest time a thread runs before it can be preempted.
+const MIN_GRANULARITY: Duration = Duration::from_millis(2);
+
+/// Weight of a thread with nice value 0.
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weight of every nice value, starting at [`NICE_MIN`].
+/// Each step changes the share of CPU time by about 10%, same as in Linux.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+/// Completely fair scheduler.
+///
+/// Every thread collects virtual runtime, the time it ran scaled by the inverse
+/// of its weight. The thread with the smallest virtual runtime runs next, so all
+/// threads get a share of the CPU proportional to their weight.
+pub struct Fair {
+    /// Queued threads ordered by their virtual runtime when they were queued.
+    queue: BTreeMap<(u64, ThreadId), Arc<Thread>>,
+    /// Never decreasing lower bound of the virtual runtimes on this hart.
+    /// Threads that slept or moved here start close to it.
+    min_vruntime: u64,
+}
+
+impl Fair {
+    pub fn new() -> Self {
+        Self { queue: BTreeMap::new(), min_vruntime: 0 }
+    }
+
+    fn weight(thread: &Thread) -> u64 {
+        NICE_TO_WEIGHT[(thread.nice() - NICE_MIN) as usize]
+    }
+
+    fn leftmost(&self) -> Option<u64> {
+        self.queue.keys().next().map(|(vruntime, _)| *vruntime)
+    }
+
+    fn update_min_vruntime(&mut self, current: u64) {
+        let smallest = self.leftmost().map_or(current, |leftmost| leftmost.min(current));
+        self.min_vruntime = self.min_vruntime.max(smallest);
+    }
+}
+
+impl Scheduler for Fair {
+    fn name(&self) -> &'static str {
+        "fair"
+    }
+
+    fn enqueue(&mut self, thread: Arc<Thread>) {
+        // Sleeping does not earn credit beyond half a latency period,
+        // otherwise a thread that slept long would monopolize the hart.
+        let floor = self.min_vruntime.saturating_sub(duration_to_ticks(SCHED_LATENCY) / 2);
+        let vruntime = thread.vruntime.load(Ordering::Relaxed).max(floor);
+        thread.vruntime.store(vruntime, Ordering::Relaxed);
+
+        self.queue.insert((vruntime, thread.id()), thread);
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        self.queue.pop_first().map(|(_, thread)| thread)
+    }
+
+    fn tick(&mut self, current: &Thread, ran: u64) -> bool {
+        let delta = ran * NICE_0_WEIGHT / Self::weight(current);
+        let vruntime = current.vruntime.fetch_add(delta, Ordering::Relaxed) + delta;
+        self.update_min_vruntime(vruntime);
+
+        self.leftmost()
+            .is_some_and(|leftmost| vruntime > leftmost + duration_to_ticks(MIN_GRANULARITY))
+    }
+
+    fn yield_current(&mut self, current: &Thread) {
+        // Go behind every queued thread.
+        if let Some((rightmost, _)) = self.queue.keys().next_back() {
+            current.vruntime.fetch_max(rightmost + 1, Ordering::Relaxed);
+        }
+    }
+
+    fn time_slice(&self) -> Duration {
+        let running = self.queue.len() as u32 + 1;
+        (SCHED_LATENCY / running).max(MIN_GRANULARITY)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn steal(&mut self) -> Option<Arc<Thread>> {
+        let key = *self.queue.iter().rev().find(|(_, thread)| !thread.is_boot_thread())?.0;
+        let thread = self.queue.remove(&key)?;
+
+        // Virtual runtimes of different harts are not comparable.
+        // The thread starts at the floor of the hart it is moved to.
+        thread.vruntime.store(0, Ordering::Relaxed);
+        Some(thread)
+    }
+}
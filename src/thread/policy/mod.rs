@@ -0,0 +1,95 @@
+pub mod fair;
+pub mod priority;
+pub mod round_robin;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use spin::Once;
+use crate::dtb;
+use crate::time::Duration;
+use super::Thread;
+
+/// Run queue of a single hart.
+///
+/// Every hart owns one instance, which is only used with interrupts disabled.
+/// The running thread is not part of the queue. It is given to [`Scheduler::tick`]
+/// and [`Scheduler::yield_current`] and queued again once it is switched out.
+pub trait Scheduler: Send {
+    fn name(&self) -> &'static str;
+
+    /// Queue a thread that is ready to run.
+    fn enqueue(&mut self, thread: Arc<Thread>);
+
+    /// Take the thread that should run next.
+    fn pick_next(&mut self) -> Option<Arc<Thread>>;
+
+    /// Charge the running thread for `ran` timebase ticks.
+    /// Returns true if it should be switched out for a queued thread.
+    fn tick(&mut self, current: &Thread, ran: u64) -> bool;
+
+    /// The running thread gives up the hart voluntarily.
+    /// Called before it is queued again.
+    fn yield_current(&mut self, current: &Thread);
+
+    /// How long the running thread may run until [`Scheduler::tick`] is called again.
+    fn time_slice(&self) -> Duration;
+
+    /// Number of queued threads.
+    fn len(&self) -> usize;
+
+    /// Remove a queued thread that is allowed to move to another hart.
+    /// Used for load balancing.
+    fn steal(&mut self) -> Option<Arc<Thread>>;
+}
+
+/// The scheduling policies the kernel can be booted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    RoundRobin,
+    Priority,
+    Fair,
+}
+
+impl Policy {
+    /// Parse the value of the `sched=` boot argument.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rr" | "round-robin" => Some(Policy::RoundRobin),
+            "prio" | "priority" => Some(Policy::Priority),
+            "fair" | "cfs" => Some(Policy::Fair),
+            _ => None,
+        }
+    }
+
+    /// Create an empty run queue of this policy.
+    pub fn create(self) -> Box<dyn Scheduler> {
+        match self {
+            Policy::RoundRobin => Box::new(round_robin::RoundRobin::new()),
+            Policy::Priority => Box::new(priority::FixedPriority::new()),
+            Policy::Fair => Box::new(fair::Fair::new()),
+        }
+    }
+}
+
+static POLICY: Once<Policy> = Once::new();
+
+/// Select the scheduling policy from the `sched=` option in the boot arguments
+/// of the device tree. Defaults to round-robin.
+pub fn init() {
+    let policy = dtb::boot_argument("sched")
+        .map(|name| {
+            Policy::from_name(name).unwrap_or_else(|| {
+                println!("| Unknown scheduler {:?}, using round-robin", name);
+                Policy::RoundRobin
+            })
+        })
+        .unwrap_or(Policy::RoundRobin);
+
+    POLICY.call_once(|| policy);
+    println!("| Scheduler: {:?}", policy);
+}
+
+/// Returns the policy selected at boot.
+pub fn get() -> Policy {
+    POLICY.get().copied().unwrap_or(Policy::RoundRobin)
+}
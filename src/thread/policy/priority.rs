@@ -0,0 +1,87 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use crate::thread::{Thread, NICE_LEVELS, NICE_MIN};
+use crate::time::{duration_to_ticks, Duration};
+use super::Scheduler;
+
+/// How long a thread runs before the next one of the same priority gets its turn.
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+/// Always runs the thread with the highest priority, round-robin among equals.
+/// The priority of a thread is its nice value, lower values run first.
+/// Threads of lower priority starve as long as higher ones are ready.
+pub struct FixedPriority {
+    levels: [VecDeque<Arc<Thread>>; NICE_LEVELS],
+    queued: usize,
+    /// Ticks the running thread used of its slice.
+    slice_used: u64,
+}
+
+impl FixedPriority {
+    pub fn new() -> Self {
+        Self {
+            levels: [const { VecDeque::new() }; NICE_LEVELS],
+            queued: 0,
+            slice_used: 0,
+        }
+    }
+
+    fn level(thread: &Thread) -> usize {
+        (thread.nice() - NICE_MIN) as usize
+    }
+
+    /// The level of the highest priority queued thread.
+    fn highest_queued(&self) -> Option<usize> {
+        self.levels.iter().position(|level| !level.is_empty())
+    }
+}
+
+impl Scheduler for FixedPriority {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn enqueue(&mut self, thread: Arc<Thread>) {
+        self.levels[Self::level(&thread)].push_back(thread);
+        self.queued += 1;
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        self.slice_used = 0;
+        let thread = self.levels[self.highest_queued()?].pop_front()?;
+        self.queued -= 1;
+        Some(thread)
+    }
+
+    fn tick(&mut self, current: &Thread, ran: u64) -> bool {
+        self.slice_used += ran;
+
+        match self.highest_queued() {
+            Some(level) if level < Self::level(current) => true,
+            Some(level) if level == Self::level(current) => self.slice_used >= duration_to_ticks(TIME_SLICE),
+            _ => false,
+        }
+    }
+
+    fn yield_current(&mut self, _current: &Thread) {}
+
+    fn time_slice(&self) -> Duration {
+        TIME_SLICE
+    }
+
+    fn len(&self) -> usize {
+        self.queued
+    }
+
+    fn steal(&mut self) -> Option<Arc<Thread>> {
+        // Take from the lowest priority, it would wait the longest here.
+        for level in self.levels.iter_mut().rev() {
+            if let Some(index) = level.iter().rposition(|thread| !thread.is_boot_thread()) {
+                self.queued -= 1;
+                return level.remove(index);
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use crate::thread::Thread;
+use crate::time::{duration_to_ticks, Duration};
+use super::Scheduler;
+
+/// How long a thread runs before the next one gets its turn.
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+/// Runs all threads in turn, each for the same time slice.
+pub struct RoundRobin {
+    queue: VecDeque<Arc<Thread>>,
+    /// Ticks the running thread used of its slice.
+    slice_used: u64,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        Self { queue: VecDeque::new(), slice_used: 0 }
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn enqueue(&mut self, thread: Arc<Thread>) {
+        self.queue.push_back(thread);
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        self.slice_used = 0;
+        self.queue.pop_front()
+    }
+
+    fn tick(&mut self, _current: &Thread, ran: u64) -> bool {
+        self.slice_used += ran;
+        self.slice_used >= duration_to_ticks(TIME_SLICE) && !self.queue.is_empty()
+    }
+
+    fn yield_current(&mut self, _current: &Thread) {}
+
+    fn time_slice(&self) -> Duration {
+        TIME_SLICE
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn steal(&mut self) -> Option<Arc<Thread>> {
+        let index = self.queue.iter().rposition(|thread| !thread.is_boot_thread())?;
+        self.queue.remove(index)
+    }
+}
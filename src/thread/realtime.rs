@@ -0,0 +1,348 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::arch::consts::MAX_HARTS;
+use crate::arch::trap::without_interrupts;
+use crate::smp::PerHart;
+use crate::time::{duration_to_ticks, timer, Duration, Instant};
+use super::{scheduler, Builder, JoinHandle, Thread, ThreadId, ThreadState, THREADS};
+
+/// Share of a hart, in parts per million, that real-time threads may reserve.
+/// The rest is left to normal threads, which include the async executor.
+pub const MAX_UTILIZATION: u64 = 900_000;
+
+/// Reserved share of every hart in parts per million.
+static UTILIZATION: PerHart<AtomicU64> = PerHart::new([const { AtomicU64::new(0) }; MAX_HARTS]);
+
+/// Timing of a periodic real-time thread.
+///
+/// Every `period` a new job is released, which may run for `runtime`
+/// and has to finish within `deadline` after its release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtParams {
+    pub runtime: Duration,
+    pub period: Duration,
+    pub deadline: Duration,
+}
+
+impl RtParams {
+    /// Parameters with the deadline at the end of the period.
+    pub fn new(runtime: Duration, period: Duration) -> Self {
+        Self { runtime, period, deadline: period }
+    }
+
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Share of a hart the thread needs in the worst case, in parts per million.
+    /// This is the density `runtime / deadline`, which is a sufficient
+    /// EDF admission test for deadlines shorter than the period.
+    pub fn utilization(&self) -> u64 {
+        (self.runtime.as_nanos() * 1_000_000 / self.deadline.as_nanos().max(1)) as u64
+    }
+
+    fn validate(&self) -> Result<(), AdmissionError> {
+        if self.runtime.is_zero() || self.runtime > self.deadline || self.deadline > self.period {
+            return Err(AdmissionError::InvalidParameters);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionError {
+    /// The parameters do not satisfy `0 < runtime <= deadline <= period`.
+    InvalidParameters,
+    /// No hart has enough unreserved time left.
+    Overloaded,
+}
+
+impl fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdmissionError::InvalidParameters => write!(f, "invalid real-time parameters"),
+            AdmissionError::Overloaded => write!(f, "real-time utilization too high"),
+        }
+    }
+}
+
+/// Counters of a real-time thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RtStats {
+    /// Jobs released.
+    pub releases: u64,
+    /// Jobs that finished.
+    pub completions: u64,
+    /// Jobs that finished after their deadline, were skipped
+    /// because the thread fell behind, or used up their runtime.
+    pub deadline_misses: u64,
+    /// Jobs that used up their runtime and were throttled.
+    pub overruns: u64,
+}
+
+/// Real-time state of a thread. All times are in timebase ticks.
+pub struct RtTask {
+    params: RtParams,
+    runtime: u64,
+    period: u64,
+    relative_deadline: u64,
+    /// The hart the thread was admitted on. It never runs anywhere else.
+    hart: usize,
+    /// Release time of the current job.
+    release: AtomicU64,
+    /// Absolute deadline of the current job.
+    deadline: AtomicU64,
+    /// Runtime the current job has left.
+    budget: AtomicU64,
+    releases: AtomicU64,
+    completions: AtomicU64,
+    deadline_misses: AtomicU64,
+    overruns: AtomicU64,
+}
+
+impl RtTask {
+    fn new(params: RtParams, hart: usize) -> Self {
+        Self {
+            params,
+            runtime: duration_to_ticks(params.runtime),
+            period: duration_to_ticks(params.period),
+            relative_deadline: duration_to_ticks(params.deadline),
+            hart,
+            release: AtomicU64::new(0),
+            deadline: AtomicU64::new(0),
+            budget: AtomicU64::new(0),
+            releases: AtomicU64::new(0),
+            completions: AtomicU64::new(0),
+            deadline_misses: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+        }
+    }
+
+    pub fn params(&self) -> RtParams {
+        self.params
+    }
+
+    pub fn hart(&self) -> usize {
+        self.hart
+    }
+
+    /// Absolute deadline of the current job in timebase ticks.
+    pub fn deadline(&self) -> u64 {
+        self.deadline.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> RtStats {
+        RtStats {
+            releases: self.releases.load(Ordering::Relaxed),
+            completions: self.completions.load(Ordering::Relaxed),
+            deadline_misses: self.deadline_misses.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Release a new job at the given time.
+    fn start_job(&self, release: u64) {
+        self.release.store(release, Ordering::Relaxed);
+        self.deadline.store(release + self.relative_deadline, Ordering::Relaxed);
+        self.budget.store(self.runtime, Ordering::Relaxed);
+        self.releases.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The first release after `now`. Jobs whose release already passed are skipped.
+    fn next_release(&self, now: u64) -> u64 {
+        let mut release = self.release.load(Ordering::Relaxed) + self.period;
+        while release <= now {
+            release += self.period;
+            self.deadline_misses.fetch_add(1, Ordering::Relaxed);
+        }
+        release
+    }
+
+    /// Runtime the current job has left, in timebase ticks.
+    pub(super) fn budget(&self) -> u64 {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    /// Charge the current job for `ran` ticks.
+    /// Only the hart running the thread changes the budget of a running job.
+    pub(super) fn charge(&self, ran: u64) {
+        self.budget.store(self.budget().saturating_sub(ran), Ordering::Relaxed);
+    }
+
+    pub(super) fn is_exhausted(&self) -> bool {
+        self.budget() == 0
+    }
+}
+
+/// Ready real-time threads of a hart, ordered by the deadline of their current job.
+pub(super) struct EdfQueue {
+    queue: BTreeMap<(u64, ThreadId), Arc<Thread>>,
+}
+
+impl EdfQueue {
+    pub(super) fn new() -> Self {
+        Self { queue: BTreeMap::new() }
+    }
+
+    pub(super) fn enqueue(&mut self, thread: Arc<Thread>) {
+        let deadline = thread.realtime.as_ref().expect("not a real-time thread").deadline();
+        self.queue.insert((deadline, thread.id), thread);
+    }
+
+    /// Take the thread with the earliest deadline.
+    pub(super) fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        self.queue.pop_first().map(|(_, thread)| thread)
+    }
+
+    /// Check if a queued thread has an earlier deadline.
+    pub(super) fn has_earlier(&self, deadline: u64) -> bool {
+        self.queue.keys().next().is_some_and(|(earliest, _)| *earliest < deadline)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// Reserve the utilization of a new real-time thread on the first hart it fits on.
+fn admit(params: &RtParams) -> Result<usize, AdmissionError> {
+    params.validate()?;
+    let utilization = params.utilization();
+
+    scheduler::harts()
+        .iter()
+        .find(|&hart| reserve(UTILIZATION.get_for(hart), utilization))
+        .ok_or(AdmissionError::Overloaded)
+}
+
+/// Add `utilization` to the reserved share of a hart, unless that exceeds [`MAX_UTILIZATION`].
+fn reserve(reserved: &AtomicU64, utilization: u64) -> bool {
+    let mut used = reserved.load(Ordering::Acquire);
+    loop {
+        if used + utilization > MAX_UTILIZATION {
+            return false;
+        }
+
+        match reserved.compare_exchange_weak(used, used + utilization, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return true,
+            Err(actual) => used = actual,
+        }
+    }
+}
+
+/// Give back the utilization of an exited real-time thread.
+pub(super) fn retire(task: &RtTask) {
+    UTILIZATION.get_for(task.hart).fetch_sub(task.params.utilization(), Ordering::AcqRel);
+}
+
+/// Returns the share of the given hart reserved by real-time threads, in parts per million.
+pub fn utilization(hart_id: usize) -> u64 {
+    UTILIZATION.get_for(hart_id).load(Ordering::Acquire)
+}
+
+/// Spawn a periodic real-time thread that calls `job` once per period,
+/// until it returns false.
+///
+/// Real-time threads run before all normal threads, the one with the earliest
+/// deadline first. A job that uses up its runtime is throttled until the next
+/// period. Fails if no hart can guarantee the deadlines of the thread.
+pub fn spawn_periodic<F>(name: impl Into<String>, params: RtParams, mut job: F) -> Result<JoinHandle<()>, AdmissionError>
+where
+    F: FnMut() -> bool + Send + 'static,
+{
+    let hart = admit(&params)?;
+    let task = Arc::new(RtTask::new(params, hart));
+    task.start_job(Instant::now().ticks());
+
+    let builder = Builder { realtime: Some(task), ..Builder::new().name(name) };
+    Ok(builder.spawn(move || {
+        while job() {
+            wait_next_period();
+        }
+    }))
+}
+
+/// Finish the current job of a real-time thread and block until the next one is released.
+///
+/// # Safety
+/// Panics if the current thread is not a real-time thread.
+pub fn wait_next_period() {
+    let thread = scheduler::current();
+    let task = thread.realtime.clone().expect("not a real-time thread");
+    drop(thread);
+
+    let now = Instant::now().ticks();
+    task.completions.fetch_add(1, Ordering::Relaxed);
+    if now > task.deadline() {
+        task.deadline_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let release = task.next_release(now);
+    scheduler::block_until(|current| {
+        let thread = current.clone();
+        timer::oneshot_at(Instant::from_ticks(release), move || {
+            task.start_job(release);
+            scheduler::wake(&thread);
+        });
+        true
+    });
+}
+
+/// Stop the running real-time thread, whose job used up its runtime,
+/// until its next job is released. The caller switches it out afterwards.
+/// Must be called with interrupts disabled.
+pub(super) fn throttle(thread: &Arc<Thread>) {
+    let task = thread.realtime.clone().expect("not a real-time thread");
+    task.overruns.fetch_add(1, Ordering::Relaxed);
+    // It can not run again before the next release, which is after the deadline.
+    task.deadline_misses.fetch_add(1, Ordering::Relaxed);
+
+    *thread.state.lock() = ThreadState::Blocked;
+    let release = task.next_release(Instant::now().ticks());
+    let thread = thread.clone();
+    timer::oneshot_at(Instant::from_ticks(release), move || {
+        task.start_job(release);
+        scheduler::wake(&thread);
+    });
+}
+
+/// Print the parameters and counters of all real-time threads.
+pub fn print_realtime() {
+    let threads: Vec<_> = without_interrupts(|| {
+        THREADS.lock().values().filter(|thread| thread.realtime.is_some()).cloned().collect()
+    });
+
+    println!("+ Real-time threads ({}):", threads.len());
+    for hart in scheduler::harts().iter() {
+        let utilization = utilization(hart);
+        println!("| Hart {}: {}.{}% reserved", hart, utilization / 10_000, utilization / 1_000 % 10);
+    }
+    for thread in threads {
+        let task = thread.realtime.as_ref().unwrap();
+        let params = task.params();
+        let stats = task.stats();
+        println!(
+            "| {} {:<12} C={:?} T={:?} D={:?} on hart {}: {} released, {} done, {} missed, {} overruns",
+            thread.id,
+            thread.name().unwrap_or("<unnamed>"),
+            params.runtime,
+            params.period,
+            params.deadline,
+            task.hart,
+            stats.releases,
+            stats.completions,
+            stats.deadline_misses,
+            stats.overruns
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::arch::consts::MAX_HARTS;
+use crate::arch::context::{self, Context};
+use crate::arch::hart_id;
+use crate::arch::trap::{disable_interrupts, enable_interrupts, interrupts_enabled, without_interrupts};
+use crate::smp::{self, HartMask, PerHart};
+use crate::task::smp_executor;
+use crate::time::timer::{self, TimerHandle};
+use crate::time::{duration_to_ticks, ticks_to_duration, Duration, Instant};
+use super::policy::{self, Scheduler};
+use super::realtime::{self, EdfQueue};
+use super::{unregister, Thread, ThreadState};
+
+/// How often a hart looks for a busier hart to take threads from.
+pub const BALANCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Ready threads of a hart. Real-time threads run before all normal threads.
+struct RunQueue {
+    realtime: EdfQueue,
+    normal: Box<dyn Scheduler>,
+}
+
+impl RunQueue {
+    fn enqueue(&mut self, thread: Arc<Thread>) {
+        if thread.realtime.is_some() {
+            self.realtime.enqueue(thread);
+        } else {
+            self.normal.enqueue(thread);
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        loop {
+            let thread = self.realtime.pick_next().or_else(|| self.normal.pick_next())?;
+            // Only ready threads may run. Skip entries left behind
+            // by a thread that was queued twice.
+            if *thread.state.lock() == ThreadState::Ready {
+                return Some(thread);
+            }
+        }
+    }
+
+    /// Charge the running thread for `ran` ticks.
+    /// Returns true if a queued thread should run instead.
+    fn tick(&mut self, current: &Thread, ran: u64) -> bool {
+        match &current.realtime {
+            Some(task) => {
+                task.charge(ran);
+                self.realtime.has_earlier(task.deadline())
+            },
+            None => self.normal.tick(current, ran) || !self.realtime.is_empty(),
+        }
+    }
+
+    fn yield_current(&mut self, current: &Thread) {
+        if current.realtime.is_none() {
+            self.normal.yield_current(current);
+        }
+    }
+
+    /// Time until the scheduler should look at the running thread again.
+    /// Real-time threads run until their job used up its runtime.
+    fn time_slice(&self, current: &Thread) -> Duration {
+        match &current.realtime {
+            Some(task) => ticks_to_duration(task.budget().max(1)),
+            None => self.normal.time_slice(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.realtime.len() + self.normal.len()
+    }
+}
+
+/// Run queue of every hart, None until [`init_hart`] was called.
+/// Queues of other harts are locked by wakeups and load balancing,
+/// so they are only locked with interrupts disabled and never two at once.
+static RUN_QUEUES: PerHart<Mutex<Option<RunQueue>>> =
+    PerHart::new([const { Mutex::new(None) }; MAX_HARTS]);
+/// Harts with a run queue.
+static SCHEDULER_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The thread running on every hart, None until [`init_hart`] was called.
+static CURRENT: PerHart<Mutex<Option<Arc<Thread>>>> =
+    PerHart::new([const { Mutex::new(None) }; MAX_HARTS]);
+/// The thread a hart just switched away from and whether it has to be
+/// queued again, see [`finish_switch`].
+static PREVIOUS: PerHart<Mutex<Option<(Arc<Thread>, bool)>>> =
+    PerHart::new([const { Mutex::new(None) }; MAX_HARTS]);
+
+/// Timebase ticks at which the running thread was last charged for its CPU time.
+static LAST_ACCOUNTED: PerHart<AtomicU64> = PerHart::new([const { AtomicU64::new(0) }; MAX_HARTS]);
+/// Timebase ticks at which each hart balances its load next.
+static NEXT_BALANCE: PerHart<AtomicU64> = PerHart::new([const { AtomicU64::new(0) }; MAX_HARTS]);
+static SLICE_TIMERS: PerHart<Mutex<Option<TimerHandle>>> =
+    PerHart::new([const { Mutex::new(None) }; MAX_HARTS]);
+
+/// Turn the code running on the current hart into its boot thread
+/// and create the run queue of the hart.
+/// Requires the kernel heap and the timers to be initialized.
+pub fn init_hart() {
+    let hart = hart_id();
+    let thread = Thread::new(Some(format!("boot/{}", hart)), Some(hart), None, None, Context::empty());
+    *thread.state.lock() = ThreadState::Running;
+    thread.on_cpu.store(true, Ordering::Release);
+
+    without_interrupts(|| {
+        *RUN_QUEUES.get().lock() = Some(RunQueue {
+            realtime: EdfQueue::new(),
+            normal: policy::get().create(),
+        });
+        *CURRENT.get().lock() = Some(thread);
+        LAST_ACCOUNTED.get().store(Instant::now().ticks(), Ordering::Relaxed);
+        start_slice();
+    });
+    SCHEDULER_HARTS.fetch_or(1 << hart, Ordering::AcqRel);
+}
+
+/// Harts that run threads.
+pub(super) fn harts() -> HartMask {
+    HartMask::from_bits(SCHEDULER_HARTS.load(Ordering::Acquire))
+}
+
+/// Run `f` on the run queue of the given hart.
+/// Must be called with interrupts disabled.
+fn with_queue<R>(hart: usize, f: impl FnOnce(&mut RunQueue) -> R) -> R {
+    let mut queue = RUN_QUEUES.get_for(hart).lock();
+    f(queue.as_mut().expect("hart has no run queue"))
+}
+
+/// Returns the name of the scheduler of normal threads on the current hart.
+pub fn name() -> &'static str {
+    without_interrupts(|| with_queue(hart_id(), |queue| queue.normal.name()))
+}
+
+/// Returns the thread running on the current hart.
+///
+/// # Safety
+/// Panics if threads have not been initialized on this hart.
+pub fn current() -> Arc<Thread> {
+    without_interrupts(|| CURRENT.get().lock().clone()).expect("threads are not initialized on this hart")
+}
+
+/// Check if a thread other than the current one is waiting for this hart.
+pub fn has_runnable() -> bool {
+    let hart = hart_id();
+    harts().contains(hart) && without_interrupts(|| with_queue(hart, |queue| queue.len() > 0))
+}
+
+/// Give up the hart to another ready thread.
+pub fn yield_now() {
+    without_interrupts(|| {
+        let thread = current();
+        with_queue(hart_id(), |queue| queue.yield_current(&thread));
+        drop(thread);
+        schedule();
+    });
+}
+
+/// Charge the running thread for the time since the last call.
+/// Returns the number of ticks it was charged.
+fn account(thread: &Thread) -> u64 {
+    let now = Instant::now().ticks();
+    let last = LAST_ACCOUNTED.get().swap(now, Ordering::Relaxed);
+    let ran = now.saturating_sub(last);
+
+    thread.cpu_time.fetch_add(ran, Ordering::Relaxed);
+    ran
+}
+
+/// Let the scheduler decide if the current thread should make room for
+/// another one. Called at the end of every interrupt.
+pub fn preempt() {
+    let hart = hart_id();
+    if !harts().contains(hart) {
+        return;
+    }
+
+    balance();
+
+    let thread = current();
+    let ran = account(&thread);
+    let mut reschedule = with_queue(hart, |queue| queue.tick(&thread, ran));
+
+    if thread.realtime.as_ref().is_some_and(|task| task.is_exhausted()) {
+        realtime::throttle(&thread);
+        reschedule = true;
+    }
+
+    if reschedule {
+        drop(thread);
+        schedule();
+    }
+}
+
+/// Mark the current thread as blocked and call `register`, which arranges
+/// for a later [`wake`]. The thread is switched out if `register` returns
+/// true, otherwise it keeps running.
+///
+/// # Safety
+/// Panics if called from a boot thread, since the async executor
+/// of the hart would stop with it.
+pub(super) fn block_until(register: impl FnOnce(&Arc<Thread>) -> bool) {
+    without_interrupts(|| {
+        let thread = current();
+        assert!(!thread.is_boot_thread(), "boot thread of hart {} can not block", hart_id());
+
+        *thread.state.lock() = ThreadState::Blocked;
+        if register(&thread) {
+            drop(thread);
+            schedule();
+            return;
+        }
+
+        let mut state = thread.state.lock();
+        if *state == ThreadState::Blocked {
+            *state = ThreadState::Running;
+        }
+    });
+}
+
+/// Make a blocked thread ready to run again. Does nothing if it is not blocked.
+/// Can be called from interrupt handlers.
+pub fn wake(thread: &Arc<Thread>) {
+    let woken = without_interrupts(|| {
+        let mut state = thread.state.lock();
+        if *state != ThreadState::Blocked {
+            return false;
+        }
+
+        *state = ThreadState::Ready;
+        true
+    });
+
+    if woken {
+        place(thread.clone());
+    }
+}
+
+/// Queue a thread that has never run.
+pub(super) fn wake_new(thread: Arc<Thread>) {
+    place(thread);
+}
+
+/// Queue a ready thread on the hart that should run it
+/// and send that hart an IPI, so that it reschedules.
+fn place(thread: Arc<Thread>) {
+    let current = hart_id();
+    let target = select_hart(&thread);
+
+    without_interrupts(|| {
+        with_queue(target, |queue| queue.enqueue(thread));
+
+        if target != current {
+            smp::kick(HartMask::single(target));
+        } else {
+            // The slice timer may have fired already while nothing was queued.
+            start_slice();
+        }
+    });
+}
+
+/// Pick the hart a woken thread is queued on. Prefers the hart it ran on last,
+/// since its caches may still be warm, then idle harts, then the shortest queue.
+fn select_hart(thread: &Thread) -> usize {
+    if let Some(hart) = thread.boot_hart {
+        return hart;
+    }
+    if let Some(task) = &thread.realtime {
+        return task.hart();
+    }
+
+    let harts = harts();
+    let last = thread.last_hart();
+    let idle = smp_executor::idle_harts().intersect(harts);
+    if idle.contains(last) {
+        return last;
+    }
+    if let Some(hart) = idle.iter().next() {
+        return hart;
+    }
+
+    without_interrupts(|| {
+        harts
+            .iter()
+            .min_by_key(|&hart| (with_queue(hart, |queue| queue.len()), hart != last))
+            .unwrap_or(hart_id())
+    })
+}
+
+/// Move a thread from the busiest hart to this one if the queue lengths differ
+/// by more than one. Runs every [`BALANCE_INTERVAL`], and whenever this hart
+/// has nothing queued. Real-time threads stay on the hart they were admitted on.
+/// Must be called with interrupts disabled.
+fn balance() {
+    let hart = hart_id();
+    let now = Instant::now().ticks();
+    let local = with_queue(hart, |queue| queue.normal.len());
+    if local > 0 && now < NEXT_BALANCE.get().load(Ordering::Relaxed) {
+        return;
+    }
+    NEXT_BALANCE.get().store(now + duration_to_ticks(BALANCE_INTERVAL), Ordering::Relaxed);
+
+    let busiest = harts()
+        .without(hart)
+        .iter()
+        .map(|other| (other, with_queue(other, |queue| queue.normal.len())))
+        .max_by_key(|&(_, len)| len);
+
+    if let Some((other, len)) = busiest {
+        if len > local + 1 {
+            // Only one queue is locked at a time, so harts balancing
+            // against each other can not deadlock.
+            if let Some(thread) = with_queue(other, |queue| queue.normal.steal()) {
+                with_queue(hart, |queue| queue.normal.enqueue(thread));
+            }
+        }
+    }
+
+    // Let an idle hart pull work from here, it does not get interrupts on its own.
+    if with_queue(hart, |queue| queue.normal.len()) > 1 {
+        if let Some(idle) = smp_executor::idle_harts().intersect(harts()).without(hart).iter().next() {
+            smp::kick(HartMask::single(idle));
+        }
+    }
+}
+
+/// Switch to the next thread of this hart's run queue.
+/// The current thread is queued again if it is still running,
+/// otherwise it waits for a [`wake`].
+///
+/// Must be called with interrupts disabled.
+fn schedule() {
+    debug_assert!(!interrupts_enabled(), "schedule called with interrupts enabled");
+
+    let hart = hart_id();
+    let previous = current();
+    let ran = account(&previous);
+    let next = with_queue(hart, |queue| {
+        queue.tick(&previous, ran);
+        queue.pick_next()
+    });
+
+    let Some(next) = next else {
+        // Only the boot thread of a hart can find the queue empty,
+        // every other thread leaves the boot thread behind in it.
+        assert!(*previous.state.lock() == ThreadState::Running, "no thread left to run on hart {}", hart);
+        start_slice();
+        return;
+    };
+
+    if Arc::ptr_eq(&previous, &next) {
+        // Woken again before it was switched out.
+        *previous.state.lock() = ThreadState::Running;
+        start_slice();
+        return;
+    }
+
+    if let Some(stack) = previous.stack.lock().as_ref() {
+        assert!(stack.is_intact(), "thread {} overflowed its stack", previous.id);
+    }
+
+    // Decide here whether the previous thread is queued again. Once it is
+    // switched out, another hart may already have woken it and set it running.
+    let requeue = {
+        let mut state = previous.state.lock();
+        let preempted = *state == ThreadState::Running;
+        if preempted {
+            *state = ThreadState::Ready;
+        }
+        preempted
+    };
+
+    // The hart that ran the thread before may still be switching away from it.
+    while next.on_cpu.load(Ordering::Acquire) {
+        spin_loop();
+    }
+    next.on_cpu.store(true, Ordering::Release);
+    *next.state.lock() = ThreadState::Running;
+    next.last_hart.store(hart, Ordering::Relaxed);
+
+    let previous_context = previous.context.get();
+    let next_context = next.context.get();
+    *CURRENT.get().lock() = Some(next);
+    *PREVIOUS.get().lock() = Some((previous, requeue));
+
+    // Both threads are kept alive by CURRENT and PREVIOUS during the switch.
+    unsafe {
+        context::switch(previous_context, next_context);
+    }
+
+    // Running again, possibly on another hart.
+    finish_switch();
+}
+
+/// Release the thread the current hart switched away from. Runs on the new
+/// thread, because the old one can not be queued while still on its stack.
+fn finish_switch() {
+    let (previous, requeue) = PREVIOUS.get().lock().take().expect("switched without a previous thread");
+    previous.on_cpu.store(false, Ordering::Release);
+
+    if requeue {
+        with_queue(hart_id(), |queue| queue.enqueue(previous));
+    } else if previous.state() == ThreadState::Exited {
+        // Nothing runs on the stack anymore.
+        previous.stack.lock().take();
+        if let Some(task) = &previous.realtime {
+            realtime::retire(task);
+        }
+        unregister(previous.id);
+    }
+
+    start_slice();
+}
+
+/// Program an interrupt at the end of the time slice of the current thread,
+/// so that the scheduler gets to decide if it keeps running.
+fn start_slice() {
+    let current = CURRENT.get().lock().clone().expect("no thread running on this hart");
+    let slice = with_queue(hart_id(), |queue| queue.time_slice(&current));
+    let end = Instant::now().saturating_add(slice);
+
+    // The timer only has to end the trap, preempting happens afterwards.
+    let previous = SLICE_TIMERS.get().lock().replace(timer::oneshot_at(end, || {}));
+    if let Some(previous) = previous {
+        previous.cancel();
+    }
+}
+
+/// First code a new thread runs, switched to by [`schedule`].
+pub(super) extern "C" fn thread_entry() -> ! {
+    finish_switch();
+    enable_interrupts();
+
+    let entry = current().entry.lock().take().expect("thread started twice");
+    entry();
+
+    exit();
+}
+
+/// Exit the current thread and wake the threads joining it.
+fn exit() -> ! {
+    disable_interrupts();
+
+    let thread = current();
+    *thread.state.lock() = ThreadState::Exited;
+    let joiners = core::mem::take(&mut *thread.joiners.lock());
+    for joiner in joiners.iter() {
+        wake(joiner);
+    }
+    drop(joiners);
+    drop(thread);
+
+    schedule();
+    unreachable!("exited thread was scheduled again");
+}
@@ -0,0 +1,92 @@
+use opensbi::time::set_timer;
+use spin::Once;
+use crate::arch::write_stimecmp;
+use crate::dtb;
+use crate::time::Instant;
+
+/// A device that raises the supervisor timer interrupt of the current hart
+/// once a deadline has passed.
+pub trait ClockEvent: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Raise the timer interrupt once `time >= deadline`.
+    /// Replaces any previously programmed deadline and clears a pending interrupt.
+    /// A deadline of [`u64::MAX`] ticks effectively disables the interrupt.
+    fn set_next_event(&self, deadline: Instant);
+}
+
+/// Programs the timer through the SBI timer extension.
+/// Every update is a round trip into machine mode.
+pub struct SbiClockEvent;
+
+impl ClockEvent for SbiClockEvent {
+    fn name(&self) -> &'static str {
+        "sbi"
+    }
+
+    fn set_next_event(&self, deadline: Instant) {
+        set_timer(deadline.ticks());
+    }
+}
+
+/// Programs the timer by writing `stimecmp` directly.
+/// Requires all harts to implement the Sstc extension.
+pub struct SstcClockEvent;
+
+impl ClockEvent for SstcClockEvent {
+    fn name(&self) -> &'static str {
+        "sstc"
+    }
+
+    fn set_next_event(&self, deadline: Instant) {
+        unsafe {
+            write_stimecmp(deadline.ticks());
+        }
+    }
+}
+
+static SBI: SbiClockEvent = SbiClockEvent;
+static SSTC: SstcClockEvent = SstcClockEvent;
+
+static CLOCK_EVENT: Once<&'static dyn ClockEvent> = Once::new();
+
+/// Select the clock event device used by all harts.
+/// Uses Sstc if every hart in the device tree supports it.
+pub fn init() {
+    let clock_event: &'static dyn ClockEvent = if all_harts_have_sstc() { &SSTC } else { &SBI };
+    let clock_event = *CLOCK_EVENT.call_once(|| clock_event);
+
+    println!("| Clock event device: {}", clock_event.name());
+}
+
+/// Returns the clock event device of the system.
+/// Falls back to the SBI timer if [`init`] has not been called yet.
+pub fn get() -> &'static dyn ClockEvent {
+    CLOCK_EVENT.get().copied().unwrap_or(&SBI)
+}
+
+/// Check if every cpu node in the device tree lists the Sstc extension.
+fn all_harts_have_sstc() -> bool {
+    let Some(cpus) = dtb::get().and_then(|tree| tree.find_node("/cpus")) else {
+        return false;
+    };
+
+    let mut cpus = cpus
+        .children()
+        .filter(|node| node.property("device_type").and_then(|p| p.as_str()) == Some("cpu"))
+        .peekable();
+
+    cpus.peek().is_some() && cpus.all(|cpu| has_sstc(&cpu))
+}
+
+/// Check the `riscv,isa-extensions` list or, on older device trees,
+/// the multi-letter extensions of the `riscv,isa` string.
+fn has_sstc(cpu: &dtb::Node) -> bool {
+    if let Some(extensions) = cpu.property("riscv,isa-extensions") {
+        return extensions.strings().any(|extension| extension == "sstc");
+    }
+
+    cpu.property("riscv,isa")
+        .and_then(|isa| isa.as_str())
+        .is_some_and(|isa| isa.split('_').skip(1).any(|extension| extension == "sstc"))
+}
@@ -0,0 +1,146 @@
+pub mod clock_event;
+pub mod sleep;
+pub mod timer;
+pub mod wall_clock;
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::arch::get_time;
+use crate::dtb;
+
+pub use core::time::Duration;
+pub use sleep::{interval, sleep, timeout};
+
+/// Timebase frequency used if the device tree does not specify one.
+/// This is the frequency QEMU virt uses.
+const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Frequency of the `time` counter in Hz.
+static TIMEBASE_FREQUENCY: AtomicU64 = AtomicU64::new(DEFAULT_TIMEBASE_FREQUENCY);
+
+/// Read the timebase frequency from the device tree and select the clock event device.
+/// Must be called after the device tree has been initialized.
+pub fn init() {
+    let frequency = dtb::get()
+        .and_then(|tree| tree.find_node("/cpus"))
+        .and_then(|cpus| cpus.property("timebase-frequency"))
+        .and_then(|property| property.as_u64())
+        .filter(|&frequency| frequency != 0);
+
+    match frequency {
+        Some(frequency) => TIMEBASE_FREQUENCY.store(frequency, Ordering::Relaxed),
+        None => println!("No timebase frequency in the device tree, assuming {} Hz", DEFAULT_TIMEBASE_FREQUENCY),
+    }
+
+    println!("| Timebase frequency: {} Hz", timebase_frequency());
+    clock_event::init();
+}
+
+/// Returns the frequency of the `time` counter in Hz.
+pub fn timebase_frequency() -> u64 {
+    TIMEBASE_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Convert a number of timebase ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SEC / timebase_frequency() as u128;
+    Duration::new((nanos / NANOS_PER_SEC) as u64, (nanos % NANOS_PER_SEC) as u32)
+}
+
+/// Convert a duration to a number of timebase ticks, rounding up,
+/// so that waiting for the result never ends too early.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let frequency = timebase_frequency() as u128;
+    let ticks = (duration.as_nanos() * frequency).div_ceil(NANOS_PER_SEC);
+    ticks.min(u64::MAX as u128) as u64
+}
+
+/// A point in time measured by the monotonic `time` counter.
+/// The counter is shared by all harts, so instants can be compared across harts.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the current point in time.
+    pub fn now() -> Self {
+        Self(get_time())
+    }
+
+    /// Create an instant from a raw value of the `time` counter.
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    /// Returns the raw value of the `time` counter.
+    pub const fn ticks(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time passed since `earlier`, or zero if `earlier` is later than `self`.
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns the time passed since this instant.
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+
+    pub fn checked_add(self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_to_ticks(duration)).map(Self)
+    }
+
+    pub fn checked_sub(self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration_to_ticks(duration)).map(Self)
+    }
+
+    /// Add a duration, clamping at the largest representable instant.
+    pub fn saturating_add(self, duration: Duration) -> Instant {
+        Self(self.0.saturating_add(duration_to_ticks(duration)))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Self::Output {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Self::Output {
+        self.checked_sub(duration).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Self::Output {
+        self.duration_since(earlier)
+    }
+}
+
+impl fmt::Debug for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Instant({:?})", ticks_to_duration(self.0))
+    }
+}
+
+/// Returns the time passed since the `time` counter started,
+/// which is roughly the time since the machine was powered on.
+pub fn uptime() -> Duration {
+    Instant::from_ticks(0).elapsed()
+}
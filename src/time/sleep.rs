@@ -0,0 +1,173 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use crate::arch::trap::without_interrupts;
+use crate::time::timer::{self, TimerHandle};
+use crate::time::{duration_to_ticks, Duration, Instant};
+
+/// The timer of a pending [`Sleep`] together with the waker it wakes.
+/// The waker is replaced if the future is polled with a different one.
+struct Registration {
+    timer: TimerHandle,
+    waker: Arc<Mutex<Waker>>,
+}
+
+/// Future returned by [`sleep`] and [`sleep_until`].
+#[must_use = "futures do nothing unless awaited"]
+pub struct Sleep {
+    deadline: Instant,
+    registration: Option<Registration>,
+}
+
+/// Wait until the given duration has passed.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now().saturating_add(duration))
+}
+
+/// Wait until the given point in time.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, registration: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Move the deadline. The future can be reused after it completed.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    fn cancel(&mut self) {
+        if let Some(registration) = self.registration.take() {
+            registration.timer.cancel();
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            self.cancel();
+            return Poll::Ready(());
+        }
+
+        match &self.registration {
+            Some(registration) => without_interrupts(|| {
+                let mut waker = registration.waker.lock();
+                if !waker.will_wake(context.waker()) {
+                    *waker = context.waker().clone();
+                }
+            }),
+            None => {
+                // The timer fires on the current hart and wakes the task
+                // through its waker, wherever the task runs by then.
+                let waker = Arc::new(Mutex::new(context.waker().clone()));
+                let timer = {
+                    let waker = waker.clone();
+                    timer::oneshot_at(self.deadline, move || waker.lock().wake_by_ref())
+                };
+                self.registration = Some(Registration { timer, waker });
+            },
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Error returned by [`Timeout`] if the deadline passed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+/// Future returned by [`timeout`].
+#[must_use = "futures do nothing unless awaited"]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Run a future, giving up once the duration has passed.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout { future, sleep: sleep(duration) }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `future` is never moved out of the pinned `Timeout`,
+        // `sleep` is `Unpin` and does not need to stay pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        if let Poll::Ready(output) = future.poll(context) {
+            return Poll::Ready(Ok(output));
+        }
+
+        match Pin::new(&mut this.sleep).poll(context) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Fires every `period`, see [`interval`].
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+/// Create an interval whose first tick completes immediately
+/// and every following one `period` after the previous.
+/// Missed ticks are skipped instead of completing all at once.
+pub fn interval(period: Duration) -> Interval {
+    assert!(duration_to_ticks(period) > 0, "interval period must not be zero");
+
+    Interval { period, sleep: sleep_until(Instant::now()) }
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Wait for the next tick. Returns the point in time the tick was scheduled for.
+    pub async fn tick(&mut self) -> Instant {
+        core::future::poll_fn(|context| self.poll_tick(context)).await
+    }
+
+    pub fn poll_tick(&mut self, context: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(context).is_pending() {
+            return Poll::Pending;
+        }
+
+        let scheduled = self.sleep.deadline();
+        let now = Instant::now();
+        let mut next = scheduled.saturating_add(self.period);
+        if next <= now {
+            next = now.saturating_add(self.period);
+        }
+        self.sleep.reset(next);
+
+        Poll::Ready(scheduled)
+    }
+}
@@ -0,0 +1,260 @@
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use core::cmp::{Ordering as CmpOrdering, Reverse};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use crate::arch::consts::MAX_HARTS;
+use crate::arch::hart_id;
+use crate::arch::trap::{enable_timer_interrupts, without_interrupts};
+use crate::smp::PerHart;
+use crate::time::{clock_event, duration_to_ticks, Duration, Instant};
+
+/// Period of the tick every hart runs.
+pub const TICK_PERIOD: Duration = Duration::from_secs(1);
+
+pub type TimerCallback = Arc<dyn Fn() + Send + Sync>;
+
+/// Unique identifier of a timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+impl TimerId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        TimerId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+struct Timer {
+    deadline: Instant,
+    id: TimerId,
+    /// Period in timebase ticks for periodic timers.
+    period: Option<u64>,
+    cancelled: Arc<AtomicBool>,
+    callback: TimerCallback,
+}
+
+// Timers are ordered by deadline, the id keeps the order
+// of timers with the same deadline stable.
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline && self.id == other.id
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.deadline, self.id).cmp(&(other.deadline, other.id))
+    }
+}
+
+/// Pending timers of a hart, the earliest deadline on top.
+static TIMER_QUEUES: PerHart<Mutex<BinaryHeap<Reverse<Timer>>>> =
+    PerHart::new([const { Mutex::new(BinaryHeap::new()) }; MAX_HARTS]);
+
+/// Number of ticks every hart has handled.
+static TICKS: PerHart<AtomicU64> = PerHart::new([const { AtomicU64::new(0) }; MAX_HARTS]);
+
+/// Handle to a timer, used to cancel it.
+/// Dropping the handle does not cancel the timer.
+pub struct TimerHandle {
+    id: TimerId,
+    hart: usize,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TimerHandle {
+    pub fn id(&self) -> TimerId {
+        self.id
+    }
+
+    /// The hart the timer fires on.
+    pub fn hart(&self) -> usize {
+        self.hart
+    }
+
+    /// Cancel the timer. Returns false if it was already cancelled
+    /// or was a one-shot timer that already fired.
+    /// A callback that is running while the timer is cancelled still finishes.
+    pub fn cancel(&self) -> bool {
+        if self.cancelled.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+
+        without_interrupts(|| {
+            let mut queue = TIMER_QUEUES.get_for(self.hart).lock();
+            let len = queue.len();
+            queue.retain(|Reverse(timer)| timer.id != self.id);
+
+            // Other harts reprogram their timer the next time it fires.
+            if self.hart == hart_id() {
+                program_next(&queue);
+            }
+
+            len != queue.len()
+        })
+    }
+}
+
+/// The tick timer of every hart, None while the tick is stopped.
+static TICK_TIMERS: PerHart<Mutex<Option<TimerHandle>>> =
+    PerHart::new([const { Mutex::new(None) }; MAX_HARTS]);
+
+/// Start the tick of the current hart and enable timer interrupts.
+/// Requires the kernel heap to be initialized.
+pub fn init_hart() {
+    start_tick();
+    enable_timer_interrupts();
+}
+
+/// Start the periodic tick of the current hart, if it is not running already.
+/// The first tick fires one period from now.
+pub fn start_tick() {
+    without_interrupts(|| {
+        let mut tick = TICK_TIMERS.get().lock();
+        if tick.is_none() {
+            *tick = Some(periodic(TICK_PERIOD, || {
+                TICKS.get().fetch_add(1, Ordering::Relaxed);
+            }));
+        }
+    });
+}
+
+/// Stop the periodic tick of the current hart.
+/// Other timers of the hart keep firing.
+pub fn stop_tick() {
+    without_interrupts(|| {
+        if let Some(tick) = TICK_TIMERS.get().lock().take() {
+            tick.cancel();
+        }
+    });
+}
+
+/// Check if the periodic tick of the current hart is running.
+pub fn tick_running() -> bool {
+    without_interrupts(|| TICK_TIMERS.get().lock().is_some())
+}
+
+/// Returns the number of ticks the current hart has handled.
+pub fn ticks() -> u64 {
+    TICKS.get().load(Ordering::Relaxed)
+}
+
+/// Call a function once the deadline has passed.
+/// Timers fire on the hart that created them. The callback runs
+/// in trap context with interrupts disabled and must not block.
+pub fn oneshot_at(deadline: Instant, callback: impl Fn() + Send + Sync + 'static) -> TimerHandle {
+    add(deadline, None, Arc::new(callback))
+}
+
+/// Call a function once after the given delay.
+/// See [`oneshot_at`] for the restrictions of the callback.
+pub fn oneshot(delay: Duration, callback: impl Fn() + Send + Sync + 'static) -> TimerHandle {
+    oneshot_at(Instant::now().saturating_add(delay), callback)
+}
+
+/// Call a function every `period`, starting one period from now.
+/// See [`oneshot_at`] for the restrictions of the callback.
+pub fn periodic(period: Duration, callback: impl Fn() + Send + Sync + 'static) -> TimerHandle {
+    let ticks = duration_to_ticks(period);
+    assert!(ticks > 0, "timer period must not be zero");
+
+    let deadline = Instant::from_ticks(Instant::now().ticks().saturating_add(ticks));
+    add(deadline, Some(ticks), Arc::new(callback))
+}
+
+fn add(deadline: Instant, period: Option<u64>, callback: TimerCallback) -> TimerHandle {
+    let id = TimerId::new();
+    let hart = hart_id();
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    without_interrupts(|| {
+        let mut queue = TIMER_QUEUES.get().lock();
+        queue.push(Reverse(Timer {
+            deadline,
+            id,
+            period,
+            cancelled: cancelled.clone(),
+            callback,
+        }));
+
+        // Only reprogram the timer if the new timer is the earliest one.
+        if queue.peek().is_some_and(|Reverse(timer)| timer.id == id) {
+            program(deadline);
+        }
+    });
+
+    TimerHandle { id, hart, cancelled }
+}
+
+/// Returns the earliest deadline of the current hart.
+pub fn next_deadline() -> Option<Instant> {
+    without_interrupts(|| TIMER_QUEUES.get().lock().peek().map(|Reverse(timer)| timer.deadline))
+}
+
+fn program(deadline: Instant) {
+    clock_event::get().set_next_event(deadline);
+}
+
+/// Program the timer interrupt for the earliest timer in the queue.
+fn program_next(queue: &BinaryHeap<Reverse<Timer>>) {
+    match queue.peek() {
+        Some(Reverse(timer)) => program(timer.deadline),
+        // Setting the deadline to the maximum also clears the pending interrupt.
+        None => program(Instant::from_ticks(u64::MAX)),
+    }
+}
+
+/// Run all expired timers of the current hart and program the
+/// timer interrupt for the next deadline. Called by the trap handler.
+pub fn handle_timer_interrupt() {
+    let queue = TIMER_QUEUES.get();
+
+    loop {
+        let now = Instant::now();
+        // Take the timer out of the queue, so that the lock is not held
+        // while the callback runs. Callbacks may add or cancel timers.
+        let expired = {
+            let mut queue = queue.lock();
+            match queue.peek() {
+                Some(Reverse(timer)) if timer.deadline <= now => queue.pop().map(|Reverse(timer)| timer),
+                _ => None,
+            }
+        };
+        let Some(mut timer) = expired else {
+            break;
+        };
+
+        if timer.cancelled.load(Ordering::Acquire) {
+            continue;
+        }
+
+        (timer.callback)();
+
+        match timer.period {
+            Some(period) if !timer.cancelled.load(Ordering::Acquire) => {
+                // Skip missed periods instead of firing them all at once.
+                let mut next = timer.deadline.ticks().saturating_add(period);
+                if next <= now.ticks() {
+                    next = now.ticks().saturating_add(period);
+                }
+                timer.deadline = Instant::from_ticks(next);
+                queue.lock().push(Reverse(timer));
+            },
+            _ => {
+                // One-shot timers can not be cancelled once they fired.
+                timer.cancelled.store(true, Ordering::Release);
+            },
+        }
+    }
+
+    program_next(&queue.lock());
+}
@@ -0,0 +1,95 @@
+use core::fmt;
+use spin::Once;
+use crate::drivers::goldfish_rtc;
+use crate::time::{Duration, Instant};
+
+/// The wall clock time at a known value of the `time` counter.
+/// Reading the RTC is a slow MMIO access, so the wall clock
+/// is derived from `rdtime` relative to this anchor instead.
+struct Anchor {
+    unix_time: Duration,
+    instant: Instant,
+}
+
+static ANCHOR: Once<Anchor> = Once::new();
+
+/// Anchor the wall clock to the real time clock.
+/// Must be called after the RTC driver has been initialized.
+pub fn init() {
+    let Some(rtc) = goldfish_rtc::get() else {
+        println!("| No real time clock, wall clock time is unavailable");
+        return;
+    };
+
+    let instant = Instant::now();
+    let unix_time = rtc.now();
+    ANCHOR.call_once(|| Anchor { unix_time, instant });
+
+    println!("| Wall clock: {}", DateTime::from_unix(unix_time));
+}
+
+/// Returns the current time since the UNIX epoch with nanosecond precision.
+/// Returns None if the system has no real time clock.
+pub fn wall_clock() -> Option<Duration> {
+    let anchor = ANCHOR.get()?;
+    Some(anchor.unix_time + anchor.instant.elapsed())
+}
+
+/// Returns the current date and time in UTC.
+/// Returns None if the system has no real time clock.
+pub fn now() -> Option<DateTime> {
+    wall_clock().map(DateTime::from_unix)
+}
+
+/// A date and time in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    /// Convert a time since the UNIX epoch to a date in the proleptic Gregorian calendar.
+    pub fn from_unix(unix_time: Duration) -> Self {
+        let seconds = unix_time.as_secs();
+        let days = (seconds / 86_400) as i64;
+        let seconds_of_day = seconds % 86_400;
+
+        // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+            nanosecond: unix_time.subsec_nanos(),
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats the date as ISO 8601, for example `2024-01-31T12:34:56.789Z`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.nanosecond / 1_000_000
+        )
+    }
+}
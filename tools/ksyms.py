@@ -0,0 +1,193 @@
+#!/usr/bin/env python3
+"""Embed the kernel symbol table into a linked kernel image.
+
+The kernel reserves a zero filled `KSYMS` area inside its `.rodata` section.
+This script reads the function symbols from the ELF symbol table, demangles
+them and writes a sorted lookup table into that area, so the panic handler
+can print symbolized backtraces without access to the ELF file.
+
+Usage:
+    ksyms.py <kernel-elf>
+    ksyms.py --then <command> [args...] <kernel-elf>
+
+The second form is meant to be used as a cargo runner: the image (the last
+argument) is patched and then `command` is executed with all arguments.
+If the environment variable `QEMU_CPU` is set, it replaces the argument of
+`-cpu` in `command`, e.g. `QEMU_CPU=rv64,sstc=off` boots without Sstc.
+"""
+
+import os
+import re
+import struct
+import sys
+
+# Must be kept in sync with `src/ksyms.rs`.
+KSYMS_MAGIC = 0x4D59534B  # "KSYM"
+KSYMS_SYMBOL = "KSYMS"
+HEADER_FORMAT = "<IIII"  # magic, count, strtab offset, strtab length
+ENTRY_FORMAT = "<QIIII"  # address, size, name offset, name length, reserved
+
+SHT_SYMTAB = 2
+STT_NOTYPE = 0
+STT_FUNC = 2
+SHF_EXECINSTR = 0x4
+
+
+def read_sections(image):
+    if image[:4] != b"\x7fELF" or image[4] != 2 or image[5] != 1:
+        raise SystemExit("ksyms: only little-endian ELF64 images are supported")
+
+    shoff, = struct.unpack_from("<Q", image, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", image, 0x3A)
+
+    sections = []
+    for i in range(shnum):
+        (name, kind, flags, addr, offset, size, link, _info, _align, entsize) = \
+            struct.unpack_from("<IIQQQQIIQQ", image, shoff + i * shentsize)
+        sections.append({
+            "name": name, "type": kind, "flags": flags, "addr": addr,
+            "offset": offset, "size": size, "link": link, "entsize": entsize,
+        })
+
+    names = sections[shstrndx]
+    for section in sections:
+        section["name"] = c_string(image, names["offset"] + section["name"])
+
+    return sections
+
+
+def read_symbols(image, sections):
+    symtab = next((s for s in sections if s["type"] == SHT_SYMTAB), None)
+    if symtab is None:
+        raise SystemExit("ksyms: image has no symbol table (was it stripped?)")
+
+    strtab = sections[symtab["link"]]
+    symbols = []
+    for offset in range(symtab["offset"], symtab["offset"] + symtab["size"], symtab["entsize"]):
+        name, info, _other, shndx, value, size = struct.unpack_from("<IBBHQQ", image, offset)
+        symbols.append({
+            "name": c_string(image, strtab["offset"] + name),
+            "type": info & 0xF,
+            "shndx": shndx,
+            "value": value,
+            "size": size,
+        })
+
+    return symbols
+
+
+def c_string(image, offset):
+    end = image.index(b"\0", offset)
+    return image[offset:end].decode("utf-8", errors="replace")
+
+
+LEGACY_ESCAPES = {
+    "$SP$": "@", "$BP$": "*", "$RF$": "&", "$LT$": "<", "$GT$": ">",
+    "$LP$": "(", "$RP$": ")", "$C$": ",",
+}
+
+
+def demangle(name):
+    """Demangles legacy Rust symbol names and strips the trailing hash."""
+    if not name.startswith("_ZN") or not name.endswith("E"):
+        return name
+
+    parts = []
+    rest = name[3:-1]
+    while rest:
+        match = re.match(r"(\d+)", rest)
+        if match is None:
+            return name
+        length = int(match.group(1))
+        start = len(match.group(1))
+        parts.append(rest[start:start + length])
+        rest = rest[start + length:]
+
+    if parts and re.fullmatch(r"h[0-9a-f]{16}", parts[-1]):
+        parts.pop()
+
+    def unescape(part):
+        if part.startswith("_$"):
+            part = part[1:]
+        for escape, replacement in LEGACY_ESCAPES.items():
+            part = part.replace(escape, replacement)
+        part = re.sub(r"\$u([0-9a-f]+)\$", lambda m: chr(int(m.group(1), 16)), part)
+        return part.replace("..", "::")
+
+    return "::".join(unescape(part) for part in parts)
+
+
+def build_table(sections, symbols):
+    functions = {}
+    for symbol in symbols:
+        if symbol["type"] not in (STT_FUNC, STT_NOTYPE) or not symbol["name"]:
+            continue
+        if symbol["shndx"] == 0 or symbol["shndx"] >= len(sections):
+            continue
+        if not sections[symbol["shndx"]]["flags"] & SHF_EXECINSTR:
+            continue
+        if symbol["name"].startswith((".L", "$")):
+            continue
+        # Prefer sized function symbols over assembler labels at the same address.
+        previous = functions.get(symbol["value"])
+        if previous is None or (previous["size"] == 0 and symbol["size"] != 0):
+            functions[symbol["value"]] = symbol
+
+    entries = b""
+    strtab = b""
+    ordered = sorted(functions.values(), key=lambda s: s["value"])
+    for symbol in ordered:
+        name = demangle(symbol["name"]).encode("utf-8")
+        entries += struct.pack(ENTRY_FORMAT, symbol["value"], symbol["size"], len(strtab), len(name), 0)
+        strtab += name
+
+    header_size = struct.calcsize(HEADER_FORMAT)
+    header = struct.pack(HEADER_FORMAT, KSYMS_MAGIC, len(ordered), header_size + len(entries), len(strtab))
+    return header + entries + strtab, len(ordered)
+
+
+def embed(path):
+    with open(path, "rb") as file:
+        image = bytearray(file.read())
+
+    sections = read_sections(image)
+    symbols = read_symbols(image, sections)
+
+    area = next((s for s in symbols if s["name"] == KSYMS_SYMBOL), None)
+    if area is None:
+        raise SystemExit(f"ksyms: `{KSYMS_SYMBOL}` not found in {path}")
+
+    section = sections[area["shndx"]]
+    offset = section["offset"] + area["value"] - section["addr"]
+
+    table, count = build_table(sections, symbols)
+    if len(table) > area["size"]:
+        raise SystemExit(f"ksyms: symbol table needs {len(table)} bytes but only {area['size']} are reserved")
+
+    image[offset:offset + area["size"]] = table + bytes(area["size"] - len(table))
+    with open(path, "wb") as file:
+        file.write(image)
+
+    print(f"ksyms: embedded {count} symbols ({len(table)} bytes) into {path}", file=sys.stderr)
+
+
+def override_cpu(command):
+    cpu = os.environ.get("QEMU_CPU")
+    if cpu and "-cpu" in command[:-1]:
+        command[command.index("-cpu") + 1] = cpu
+    return command
+
+
+def main(argv):
+    if len(argv) >= 3 and argv[1] == "--then":
+        command = override_cpu(argv[2:])
+        embed(command[-1])
+        os.execvp(command[0], command)
+    elif len(argv) == 2:
+        embed(argv[1])
+    else:
+        raise SystemExit(__doc__)
+
+
+if __name__ == "__main__":
+    main(sys.argv)